@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use crate::lexer::{Span, Token};
+use crate::parser::{Expr, Path, Stmt};
+use crate::resolve::suggest;
+
+struct Folder {
+    /// Constants defined so far, with their value and where they were defined.
+    consts: HashMap<String, (f64, Span)>,
+    /// Enums defined so far, with where each was defined and its members' values in order.
+    enums: HashMap<String, (Span, Vec<(String, i64)>)>,
+    /// Where each local was first declared, so a constant can't take its name.
+    locals: HashMap<String, Span>,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+/// Replaces every use of a `const` or enum member with its value, and folds integer
+/// arithmetic on constants, so `MAX_HP - 1` reaches the later passes as `99`.
+/// Constants are visible from their definition to the end of the program.
+/// Returns errors and warnings, each starting with the `line:col` it points at.
+pub fn fold(ast: &mut [Stmt]) -> (Vec<String>, Vec<String>) {
+    let mut f = Folder {
+        consts: HashMap::new(),
+        enums: HashMap::new(),
+        locals: HashMap::new(),
+        errors: Vec::new(),
+        warnings: Vec::new(),
+    };
+    f.block(ast);
+    (f.errors, f.warnings)
+}
+
+impl Folder {
+    fn block(&mut self, stmts: &mut [Stmt]) {
+        for s in stmts { self.stmt(s); }
+    }
+
+    /// Where `name` is already taken by a constant or an enum.
+    fn defined(&self, name: &str) -> Option<Span> {
+        self.consts.get(name).map(|(_, s)| *s).or_else(|| self.enums.get(name).map(|(s, _)| *s))
+    }
+
+    fn declare(&mut self, name: &str, span: Span) {
+        if let Some(def) = self.defined(name) {
+            self.errors.push(format!("{}: `{}` is a constant defined at {}", span, name, def));
+        }
+        self.locals.entry(name.to_string()).or_insert(span);
+    }
+
+    /// The value of a constant expression that has already been folded.
+    fn value(&mut self, e: &mut Expr, what: &str, span: Span) -> Option<f64> {
+        self.expr(e);
+        match e {
+            Expr::Number(n) => Some(*n),
+            _ => {
+                self.errors.push(format!("{}: the value of `{}` must be a constant number", span, what));
+                None
+            }
+        }
+    }
+
+    /// Looks `name` up as a constant that fits `what`, like an array length.
+    fn whole(&mut self, name: &str, span: Span, what: &str) -> Option<usize> {
+        match self.consts.get(name) {
+            Some((n, _)) if n.fract() == 0.0 && *n >= 0.0 => Some(*n as usize),
+            Some((n, _)) => {
+                self.errors.push(format!("{}: {} must be a whole number, but `{}` is {}", span, what, name, n));
+                None
+            }
+            None => {
+                let hint = suggest(name, self.consts.keys());
+                self.errors.push(format!("{}: `{}` is not a constant{}", span, name, hint));
+                None
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Const { name, value, span } => {
+                let span = *span;
+                let value = self.value(value, name, span);
+                if let Some(prev) = self.defined(name).or_else(|| self.locals.get(name.as_str()).copied()) {
+                    self.errors.push(format!("{}: `{}` is already defined at {}", span, name, prev));
+                    return;
+                }
+                if let Some(n) = value { self.consts.insert(name.clone(), (n, span)); }
+            }
+            Stmt::EnumDef { name, variants, span } => {
+                let mut members: Vec<(String, i64)> = Vec::new();
+                let mut spans: Vec<Span> = Vec::new();
+                let mut next = 0;
+                for v in variants.iter_mut() {
+                    let what = format!("{}.{}", name, v.name);
+                    if let Some(e) = &mut v.value {
+                        match self.value(e, &what, v.span) {
+                            Some(n) if n.fract() == 0.0 => next = n as i64,
+                            Some(n) => self.errors.push(format!("{}: `{}` must be an integer, found {}", v.span, what, n)),
+                            None => {}
+                        }
+                    }
+                    if let Some(i) = members.iter().position(|(m, _)| m == &v.name) {
+                        self.errors.push(format!("{}: member `{}` is already defined in `{}` at {}", v.span, v.name, name, spans[i]));
+                        continue;
+                    }
+                    members.push((v.name.clone(), next));
+                    spans.push(v.span);
+                    next = next.wrapping_add(1);
+                }
+                if let Some(prev) = self.defined(name).or_else(|| self.locals.get(name.as_str()).copied()) {
+                    self.errors.push(format!("{}: `{}` is already defined at {}", span, name, prev));
+                    return;
+                }
+                self.enums.insert(name.clone(), (*span, members));
+            }
+            Stmt::ClassDef { fields, .. } => {
+                for f in fields {
+                    if let Some(c) = f.len_const.take() { f.len = self.whole(&c, f.span, "an array length"); }
+                    if let Some(c) = f.offset_const.take() { f.offset = self.whole(&c, f.span, "a field offset"); }
+                }
+            }
+            Stmt::LocalAssign { name, value, span, .. } => {
+                self.expr(value);
+                self.declare(name, *span);
+            }
+            Stmt::GlobalDef { name, value, span, .. } => {
+                if let Some(v) = value { self.expr(v); }
+                self.declare(name, *span);
+            }
+            Stmt::HeapAlloc { var_name, class_name, span } | Stmt::StackAlloc { var_name, class_name, span } => {
+                // `new [N]u64` with `N` a constant.
+                if let Some((len, elem)) = class_name.strip_prefix('[').and_then(|t| t.split_once(']'))
+                    && len.parse::<usize>().is_err()
+                    && let Some(n) = self.whole(len, *span, "an array length")
+                {
+                    *class_name = format!("[{}]{}", n, elem);
+                }
+                self.declare(var_name, *span);
+            }
+            Stmt::FieldAssign { path, value: e } | Stmt::FieldMath { path, rhs: e, .. } => {
+                self.expr(e);
+                if let Some(i) = &mut path.index { self.expr(i); }
+                if self.defined(&path[0]).is_some() {
+                    self.errors.push(format!("{}: cannot assign to constant `{}`", path.span, path.join(".")));
+                }
+            }
+            Stmt::IfStmt { cond, body, .. } | Stmt::WhileStmt { cond, body, .. } => {
+                self.expr(cond);
+                self.block(body);
+            }
+            Stmt::ForStmt { var, start, end, step, body, span, .. } => {
+                self.expr(start);
+                self.expr(end);
+                if let Some(step) = step { self.expr(step); }
+                self.declare(var, *span);
+                self.block(body);
+            }
+            Stmt::Match { value, arms, span } => {
+                self.expr(value);
+                self.check_exhaustive(arms.iter().map(|a| a.values.as_slice()), *span);
+                for arm in arms {
+                    for v in &mut arm.values { self.expr(v); }
+                    self.block(&mut arm.body);
+                }
+            }
+            Stmt::Print { items, .. } => for e in items { self.expr(e) },
+            Stmt::Free { value: e, .. } | Stmt::Exit { status: e, .. } => self.expr(e),
+            Stmt::ProbIf { chance, chance_const, body, span } => {
+                if let Some(c) = chance_const.take() {
+                    *chance = self.whole(&c, *span, "a chance").unwrap_or(0) as f64;
+                }
+                self.block(body);
+            }
+            Stmt::Arena(body) | Stmt::MergeBlock(body) => self.block(body),
+            Stmt::Break { .. } | Stmt::Continue { .. }
+            | Stmt::AsmBlock(_) | Stmt::IntelBlock(_) | Stmt::PythonBlock(_) => {}
+        }
+    }
+
+    /// Warns about a `match` without a `_` arm whose arms are all members of one
+    /// enum but leave some of its members out.
+    fn check_exhaustive<'a>(&mut self, mut arms: impl Iterator<Item = &'a [Expr]>, span: Span) {
+        let mut name = None;
+        let mut seen = Vec::new();
+        let all_members = arms.all(|values| {
+            !values.is_empty() && values.iter().all(|v| {
+                let Expr::Path(p) = v else { return false };
+                let Some((_, members)) = self.enums.get(&p[0]) else { return false };
+                if p.len() != 2 || p.index.is_some() || *name.get_or_insert(&p[0]) != &p[0] { return false; }
+                seen.extend(members.iter().find(|(m, _)| m == &p[1]).map(|(_, n)| *n));
+                true
+            })
+        });
+        let Some(name) = name.filter(|_| all_members) else { return };
+        let missing: Vec<String> = self.enums[name].1.iter()
+            .filter(|(_, n)| !seen.contains(n))
+            .map(|(m, _)| format!("`{}.{}`", name, m))
+            .collect();
+        if !missing.is_empty() {
+            self.warnings.push(format!("{}: `match` on `{}` doesn't handle {}; add an arm or `_`", span, name, missing.join(", ")));
+        }
+    }
+
+    fn expr(&mut self, e: &mut Expr) {
+        let folded = match e {
+            Expr::Path(p) => {
+                if let Some(i) = &mut p.index { self.expr(i); }
+                self.lookup(p)
+            }
+            Expr::Neg(inner) => {
+                self.expr(inner);
+                if let Expr::Number(n) = **inner { Some(-n) } else { None }
+            }
+            Expr::Format { value, .. } => { self.expr(value); None }
+            Expr::Interp(parts) => {
+                for p in parts { self.expr(p); }
+                None
+            }
+            Expr::Binary { op, lhs, rhs } => {
+                self.expr(lhs);
+                self.expr(rhs);
+                match (&**lhs, &**rhs) {
+                    (Expr::Number(a), Expr::Number(b)) => eval(op, *a, *b),
+                    _ => None,
+                }
+            }
+            Expr::Call { args, .. } => {
+                for a in args { self.expr(a); }
+                None
+            }
+            Expr::Slice { value, start, end } => {
+                self.expr(value);
+                for bound in [start, end].into_iter().flatten() { self.expr(bound); }
+                None
+            }
+            Expr::Number(_) | Expr::Str(_) | Expr::Bool(_) => None,
+        };
+        if let Some(n) = folded { *e = Expr::Number(n); }
+    }
+
+    /// The value of `MAX` or `State.Idle`; anything else is left to `resolve`.
+    fn lookup(&mut self, p: &Path) -> Option<f64> {
+        if p.index.is_some() { return None; }
+        match p.len() {
+            1 => self.consts.get(&p[0]).map(|(n, _)| *n),
+            2 => {
+                let (_, members) = self.enums.get(&p[0])?;
+                let found = members.iter().find(|(m, _)| m == &p[1]).map(|(_, n)| *n as f64);
+                if found.is_none() {
+                    let hint = suggest(&p[1], members.iter().map(|(m, _)| m));
+                    self.errors.push(format!("{}: `{}` has no member `{}`{}", p.span, p[0], p[1], hint));
+                }
+                found
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `a op b` on two constants. Whole numbers use the 64-bit integer arithmetic the
+/// generated code would, and the signed shift; division by zero is left for run time.
+/// Other numbers only fold when the result keeps a fraction, so it stays a float.
+fn eval(op: &Token, a: f64, b: f64) -> Option<f64> {
+    if a.fract() == 0.0 && b.fract() == 0.0 {
+        let (a, b) = (a as i64, b as i64);
+        let n = match op {
+            Token::Plus => a.wrapping_add(b),
+            Token::Minus => a.wrapping_sub(b),
+            Token::Star => a.wrapping_mul(b),
+            Token::Slash if b != 0 => a.wrapping_div(b),
+            Token::Percent if b != 0 => a.wrapping_rem(b),
+            Token::Amp => a & b,
+            Token::Pipe => a | b,
+            Token::Caret => a ^ b,
+            Token::Shl => a.wrapping_shl(b as u32),
+            Token::Shr => a.wrapping_shr(b as u32),
+            _ => return None,
+        };
+        return Some(n as f64);
+    }
+    let n = match op {
+        Token::Plus => a + b,
+        Token::Minus => a - b,
+        Token::Star => a * b,
+        Token::Slash => a / b,
+        _ => return None,
+    };
+    (n.fract() != 0.0).then_some(n)
+}
@@ -1,61 +1,869 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::process::Command;
-use crate::lexer::{Lexer, Token};
-use crate::parser::{Parser, Stmt};
+use crate::layout::{self, ClassLayout, FieldLayout, FieldType};
+use crate::lexer::{Span, Token};
+use crate::parser::{is_compare, Expr, FormatSpec, MatchArm, Path, Stmt};
+use crate::peephole;
+use crate::runtime::Runtime;
+
+pub struct Options {
+    /// Expand print, alloc and roll code at every use instead of calling the runtime.
+    /// Formatted values (`{x:08x}`) always go through `__hamer_format`.
+    pub inline_runtime: bool,
+    /// Bytes mapped for the heap at startup; also the size of each chunk it grows by.
+    pub heap_size: usize,
+    /// Give `new` objects a type header and reclaim them with a mark-sweep collector.
+    pub gc: bool,
+    /// Check every computed array index against the length and trap when it's out of range.
+    pub bounds_check: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { inline_runtime: false, heap_size: 4096, gc: false, bounds_check: false }
+    }
+}
+
+/// Where `break` and `continue` go for one enclosing loop.
+struct Loop {
+    label: Option<String>,
+    id: usize,
+    /// The `continue` target: the loop test, or a `for` loop's increment.
+    next: String,
+    /// `stack_bytes` and the number of open arenas just outside the loop body.
+    stack_bytes: usize,
+    arenas: usize,
+}
+
+/// What a nested block puts back at its `done`.
+struct BlockState {
+    stack_bytes: usize,
+    symbols: HashMap<String, String>,
+    obj_types: HashMap<String, String>,
+    var_types: HashMap<String, String>,
+    reg_count: usize,
+    freg_count: usize,
+    scope: HashSet<String>,
+}
 
 pub struct Generator {
     pub output: String,
+    /// Programs that need more registers than there are, each message starting with a `line:col`.
+    pub errors: Vec<String>,
     symbols: HashMap<String, String>,
-    class_map: HashMap<String, Vec<String>>,
+    /// Globals by name with the type of their slot in `.data` or `.bss`.
+    globals: HashMap<String, FieldType>,
+    class_map: HashMap<String, ClassLayout>,
     obj_types: HashMap<String, String>,
+    var_types: HashMap<String, String>,
+    /// Next x register for a local: x12..x19, then x21..x28 past the heap pointer.
     reg_count: usize,
+    /// Next d register for a float local; d8..d15 survive runtime calls.
+    freg_count: usize,
+    /// Locals declared by the innermost block. Declaring any other name gives it a fresh
+    /// register, shadowing an outer local of the same name until the block ends.
+    scope: HashSet<String>,
     label_count: usize,
+    opts: Options,
+    runtime: Runtime,
+    /// Set when the program frees objects, so every allocation goes through the free lists.
+    uses_free: bool,
+    /// Bytes of `stack` objects and arena save areas currently below the starting sp.
+    stack_bytes: usize,
+    /// The `stack_bytes` at which each open arena saved the heap state, innermost last.
+    arenas: Vec<usize>,
+    loops: Vec<Loop>,
 }
 
 impl Generator {
-    pub fn new() -> Self {
+    pub fn new(opts: Options) -> Self {
+        // x20 is the heap bump pointer; `__hamer_heap_end` marks where the current chunk stops.
+        let heap = Self::load_imm("x1", opts.heap_size as i64);
+        let mut runtime = Runtime::default();
+        runtime.gc = opts.gc;
+        runtime.require("__hamer_oom");
+        // The collector scans the stack from its current top up to where `_start` found it.
+        let stack_base = if opts.gc {
+            "    mov x1, sp\n    adrp x2, __hamer_stack_base\n    str x1, [x2, :lo12:__hamer_stack_base]\n"
+        } else {
+            ""
+        };
         Self {
-            output: ".global _start\n.section .text\n\n_start:\n    mov x11, #10\n    mov x0, #0\n    mov x1, #4096\n    mov x2, #3\n    mov x3, #34\n    mov x4, #-1\n    mov x5, #0\n    mov x8, #222\n    svc #0\n    mov x20, x0\n".to_string(),
+            errors: Vec::new(),
+            output: format!(".global _start\n.section .bss\n.balign 8\n__hamer_heap_end: .skip 8\n__hamer_heap_chunk: .skip 8\n__hamer_maps: .skip 8\n.section .text\n\n_start:\n    mov x11, #10\n    mov x0, #0\n{}    mov x2, #3\n    mov x3, #34\n    mov x4, #-1\n    mov x5, #0\n    mov x8, #222\n    svc #0\n    cmn x0, #4096\n    b.hi __hamer_oom\n    mov x20, x0\n    adrp x2, __hamer_heap_chunk\n    str x1, [x2, :lo12:__hamer_heap_chunk]\n    add x1, x0, x1\n    adrp x2, __hamer_heap_end\n    str x1, [x2, :lo12:__hamer_heap_end]\n{}", heap, stack_base),
             symbols: HashMap::new(),
+            globals: HashMap::new(),
             class_map: HashMap::new(),
             obj_types: HashMap::new(),
+            var_types: HashMap::new(),
             reg_count: 12,
+            freg_count: 8,
+            scope: HashSet::new(),
             label_count: 0,
+            opts,
+            runtime,
+            uses_free: false,
+            stack_bytes: 0,
+            arenas: Vec::new(),
+            loops: Vec::new(),
         }
     }
 
-    fn get_path_info(&self, path: &Vec<String>) -> (String, usize) {
+    /// The type of the global a path names, unless a local hides it.
+    fn global(&self, path: &Path) -> Option<FieldType> {
+        if !path.is_local() || self.symbols.contains_key(&path[0]) { return None; }
+        self.globals.get(&path[0]).copied()
+    }
+
+    /// Whether a path names a local kept in a register.
+    fn in_reg(&self, path: &Path) -> bool {
+        path.is_local() && self.global(path).is_none()
+    }
+
+    fn get_path_info(&self, path: &Path) -> (String, usize, FieldType) {
+        if let Some(ty) = self.global(path) { return ("x6".into(), 0, ty); }
         let base_var = &path[0];
         let reg = self.symbols.get(base_var).cloned().unwrap_or("x0".to_string());
-        let mut offset = 0;
-        if path.len() > 1 {
-            if let Some(c) = self.obj_types.get(base_var) {
-                if let Some(fields) = self.class_map.get(c) {
-                    offset = fields.iter().position(|f| f == &path[1]).unwrap_or(0) * 8;
+        let (mut offset, mut ty) = (0, FieldType::Any);
+        if !self.in_reg(path)
+            && let Some(f) = self.path_field(path)
+        {
+            (offset, ty) = (f.offset, f.ty);
+        }
+        (reg, offset, ty)
+    }
+
+    /// The field a path names, or for an indexed array local, the array's elements.
+    fn path_field(&self, path: &[String]) -> Option<&FieldLayout> {
+        let layout = self.class_map.get(self.obj_types.get(&path[0])?)?;
+        match path.get(1) {
+            Some(name) => layout.field(name),
+            None => layout.fields.first().filter(|f| f.name.is_empty()),
+        }
+    }
+
+    /// The length of the array a local or field holds, if it holds one.
+    fn array_len(&self, path: &Path) -> Option<usize> {
+        if path.index.is_some() { return None; }
+        match path.len() {
+            1 => layout::array_type(self.obj_types.get(&path[0])?).map(|(_, len)| len),
+            _ => self.path_field(path)?.len,
+        }
+    }
+
+    /// Emits what a field or element address needs and returns its type and address operand.
+    /// A computed index goes in x5, and the start of an array field or the page of a global in x6.
+    fn gen_addr(&mut self, path: &Path) -> (FieldType, String) {
+        if let Some(ty) = self.global(path) {
+            let sym = global_symbol(&path[0]);
+            self.output.push_str(&format!("    adrp x6, {}\n", sym));
+            return (ty, format!("[x6, :lo12:{}]", sym));
+        }
+        let (reg, offset, ty) = self.get_path_info(path);
+        let Some(index) = &path.index else {
+            return (ty, format!("[{}, #{}]", reg, offset));
+        };
+        let len = self.path_field(path).and_then(|f| f.len).unwrap_or(0);
+        if let Expr::Number(n) = **index
+            && (0.0..len as f64).contains(&n)
+        {
+            // Constant indices fold into the offset; the type checker rejects the ones out of range.
+            return (ty, format!("[{}, #{}]", reg, offset + n as usize * ty.size()));
+        }
+        self.gen_expr(index, "x5");
+        if self.opts.bounds_check {
+            self.runtime.require("__hamer_bounds");
+            self.output.push_str(&Self::load_imm("x6", len as i64));
+            self.output.push_str("    cmp x5, x6\n    b.hs __hamer_bounds\n");
+        }
+        if offset == 0 {
+            return (ty, ty.element(&reg, "x5"));
+        }
+        self.output.push_str(&format!("    add x6, {}, #{}\n", reg, offset));
+        (ty, ty.element("x6", "x5"))
+    }
+
+    /// Reads a local, field or element into `dst`, extending narrow types to 64 bits.
+    fn load_path(&mut self, path: &Path, dst: &str) {
+        if self.in_reg(path) {
+            let (reg, _, _) = self.get_path_info(path);
+            if reg != dst { self.output.push_str(&format!("    mov {}, {}\n", dst, reg)); }
+            return;
+        }
+        let (ty, addr) = self.gen_addr(path);
+        self.output.push_str(&ty.load(dst, &addr));
+    }
+
+    /// Whether reading `path` into x10 leaves `dst` alone. An index is computed in x5,
+    /// and one that calls into the runtime clobbers x0-x8, so anything but a constant
+    /// or a plain local or field as the index needs `dst` spilled first.
+    fn reads_in_place(&self, path: &Path, dst: &str) -> bool {
+        match path.index.as_deref() {
+            None => true,
+            Some(Expr::Number(_)) => dst != "x5",
+            Some(Expr::Path(i)) => i.index.is_none() && dst != "x5",
+            Some(_) => false,
+        }
+    }
+
+    /// Locals are signed unless declared unsigned; object references print as addresses.
+    fn is_signed(&self, name: &str) -> bool {
+        !self.obj_types.contains_key(name)
+            && self.var_types.get(name).is_none_or(|t| !matches!(t.as_str(), "u8" | "u16" | "u32" | "u64"))
+    }
+
+    /// Wraps a local declared with a narrow integer type after a computed write.
+    fn narrow_local(&mut self, name: &str) {
+        let ty = self.var_types.get(name).and_then(|t| FieldType::parse(t));
+        if let Some(ty) = ty {
+            let reg = self.symbols[name].clone();
+            self.output.push_str(&ty.narrow(&reg));
+        }
+    }
+
+    fn expr_signed(&self, e: &Expr) -> bool {
+        match e {
+            Expr::Path(p) if self.in_reg(p) => self.is_signed(&p[0]),
+            Expr::Path(p) => self.get_path_info(p).2.signed(),
+            Expr::Binary { lhs, rhs, .. } => self.expr_signed(lhs) || self.expr_signed(rhs),
+            // A literal that isn't negative reads the same either way, so the other operand decides.
+            Expr::Number(n) => *n < 0.0,
+            _ => true,
+        }
+    }
+
+    /// Whether a local or field holds a float. Float locals live in d registers.
+    fn is_float_path(&self, path: &Path) -> bool {
+        if !self.in_reg(path) {
+            self.get_path_info(path).2 == FieldType::F64
+        } else {
+            self.symbols.get(&path[0]).is_some_and(|r| r.starts_with('d'))
+        }
+    }
+
+    /// A float literal or anything that reads a float makes the whole expression a float.
+    fn expr_float(&self, e: &Expr) -> bool {
+        match e {
+            Expr::Number(n) => n.fract() != 0.0,
+            Expr::Path(p) => self.is_float_path(p),
+            Expr::Neg(e) | Expr::Format { value: e, .. } => self.expr_float(e),
+            Expr::Binary { op, .. } if is_compare(op) => false,
+            Expr::Binary { lhs, rhs, .. } => self.expr_float(lhs) || self.expr_float(rhs),
+            Expr::Str(_) | Expr::Interp(_) | Expr::Call { .. } | Expr::Slice { .. } | Expr::Bool(_) => false,
+        }
+    }
+
+    /// Whether an expression gives a bool, kept as 0 or 1.
+    fn expr_bool(&self, e: &Expr) -> bool {
+        match e {
+            Expr::Bool(_) => true,
+            Expr::Binary { op, .. } => is_compare(op),
+            Expr::Path(p) if self.in_reg(p) => self.var_types.get(&p[0]).is_some_and(|t| t == "bool"),
+            Expr::Path(p) => self.get_path_info(p).2 == FieldType::Bool,
+            Expr::Format { value, .. } => self.expr_bool(value),
+            _ => false,
+        }
+    }
+
+    /// Whether an expression gives a string: a reference to its length and bytes.
+    fn expr_str(&self, e: &Expr) -> bool {
+        match e {
+            Expr::Str(_) | Expr::Slice { .. } => true,
+            Expr::Path(p) if self.in_reg(p) => self.var_types.get(&p[0]).is_some_and(|t| t == "str"),
+            Expr::Path(p) => self.get_path_info(p).2 == FieldType::Str,
+            Expr::Format { value, .. } => self.expr_str(value),
+            Expr::Binary { op: Token::Plus, lhs, rhs } => self.expr_str(lhs) || self.expr_str(rhs),
+            Expr::Call { name, .. } => name == "str",
+            _ => false,
+        }
+    }
+
+    /// Emits a string literal into `.data`, laid out like a heap string, and returns its label.
+    fn str_literal(&mut self, s: &str) -> String {
+        let id = self.label_count; self.label_count += 1;
+        self.output.push_str(&format!("\n.section .data\n.balign 8\n.Lstr{}: .quad {}\n    .ascii \"{}\"\n.section .text\n", id, s.len(), s));
+        format!(".Lstr{}", id)
+    }
+
+    /// Calls a runtime routine with `args` in x0, x1, ... and puts the result in `dst`.
+    /// Enclosing expressions keep temporaries in x1..x6, so those survive the call.
+    fn gen_call(&mut self, routine: &'static str, args: &[&Expr], dst: &str) {
+        self.output.push_str("    stp x1, x2, [sp, #-16]!\n    stp x3, x4, [sp, #-16]!\n    stp x5, x6, [sp, #-16]!\n");
+        let last = args.len().saturating_sub(1);
+        for (i, arg) in args.iter().enumerate() {
+            // Every argument is built in x0, which no operator uses for its own scratch.
+            self.gen_expr(arg, "x0");
+            if i < last {
+                self.output.push_str("    str x0, [sp, #-16]!\n");
+            } else if i > 0 {
+                self.output.push_str(&format!("    mov x{}, x0\n", i));
+            }
+        }
+        for i in (0..last).rev() {
+            self.output.push_str(&format!("    ldr x{}, [sp], #16\n", i));
+        }
+        let call = self.runtime.call(routine);
+        self.output.push_str(&call);
+        self.output.push_str("    ldp x5, x6, [sp], #16\n    ldp x3, x4, [sp], #16\n    ldp x1, x2, [sp], #16\n");
+        if dst != "x0" { self.output.push_str(&format!("    mov {}, x0\n", dst)); }
+    }
+
+    /// Loads a float constant into the d register `dst` through x9.
+    fn load_fimm(dst: &str, v: f64) -> String {
+        if v.to_bits() == 0 {
+            return format!("    fmov {}, xzr\n", dst);
+        }
+        format!("{}    fmov {}, x9\n", Self::load_imm("x9", v.to_bits() as i64), dst)
+    }
+
+    /// Loads any 64-bit constant; `mov` only encodes small ones.
+    fn load_imm(dst: &str, v: i64) -> String {
+        if (-65536..65536).contains(&v) {
+            format!("    mov {}, #{}\n", dst, v)
+        } else {
+            format!("    ldr {}, ={}\n", dst, v)
+        }
+    }
+
+    /// Evaluates `e` into `dst`. x10 holds the right operand and
+    /// nested right-hand sides are parked on the stack. Operators keep their own
+    /// temporaries in x7 and x8, so neither may ever be passed as `dst`.
+    fn gen_expr(&mut self, e: &Expr, dst: &str) {
+        if self.expr_float(e) {
+            // Floats used as integers truncate toward zero.
+            self.gen_fexpr(e, "d16");
+            self.output.push_str(&format!("    fcvtzs {}, d16\n", dst));
+            return;
+        }
+        match e {
+            Expr::Number(n) => self.output.push_str(&Self::load_imm(dst, *n as i64)),
+            Expr::Bool(b) => self.output.push_str(&format!("    mov {}, #{}\n", dst, *b as u8)),
+            Expr::Binary { op, lhs, rhs } if is_compare(op) => {
+                let cond = self.gen_compare(lhs, op, rhs, dst);
+                self.output.push_str(&format!("    cset {}, {}\n", dst, cond));
+            }
+            Expr::Str(s) => {
+                let label = self.str_literal(s);
+                self.output.push_str(&format!("    adr {}, {}\n", dst, label));
+            }
+            // Interpolated strings only print; the type checker rejects them anywhere else.
+            Expr::Interp(_) => self.output.push_str(&format!("    mov {}, #0\n", dst)),
+            Expr::Binary { lhs, rhs, .. } if self.expr_str(e) => self.gen_call("__hamer_str_concat", &[lhs, rhs], dst),
+            Expr::Call { name, args, .. } => match (name.as_str(), args.first()) {
+                ("len", Some(Expr::Path(p))) if self.array_len(p).is_some() => {
+                    let len = self.array_len(p).unwrap_or(0);
+                    self.output.push_str(&Self::load_imm(dst, len as i64));
+                }
+                ("len", Some(s)) => {
+                    self.gen_expr(s, dst);
+                    self.output.push_str(&format!("    ldr {}, [{}]\n", dst, dst));
+                }
+                ("str", Some(n)) => self.gen_call("__hamer_str_from_int", &[n], dst),
+                ("int", Some(s)) => self.gen_call("__hamer_str_to_int", &[s], dst),
+                _ => self.output.push_str(&format!("    mov {}, #0\n", dst)),
+            },
+            Expr::Slice { value, start, end } => {
+                // A missing end is -1, which the runtime clamps to the length.
+                let start = start.as_deref().cloned().unwrap_or(Expr::Number(0.0));
+                let end = end.as_deref().cloned().unwrap_or(Expr::Number(-1.0));
+                self.gen_call("__hamer_str_slice", &[value, &start, &end], dst);
+            }
+            Expr::Format { value, .. } => self.gen_expr(value, dst),
+            Expr::Path(path) => self.load_path(path, dst),
+            Expr::Neg(inner) => {
+                self.gen_expr(inner, dst);
+                self.output.push_str(&format!("    neg {}, {}\n", dst, dst));
+            }
+            Expr::Binary { op, lhs, rhs } => {
+                self.gen_expr(lhs, dst);
+                let signed = self.expr_signed(e);
+                match rhs.as_ref() {
+                    Expr::Number(n) => {
+                        self.gen_int_op_imm(op, signed, dst, *n as i64);
+                        return;
+                    }
+                    Expr::Path(p) if self.reads_in_place(p, dst) => self.gen_expr(rhs, "x10"),
+                    _ => {
+                        self.output.push_str(&format!("    str {}, [sp, #-16]!\n", dst));
+                        self.gen_expr(rhs, dst);
+                        self.output.push_str(&format!("    mov x10, {}\n    ldr {}, [sp], #16\n", dst, dst));
+                    }
+                }
+                self.gen_int_op(op, signed, dst, "x10");
+            }
+        }
+    }
+
+    /// Emits `dst = dst op rhs` on 64-bit integers. Dividing by zero jumps to the
+    /// runtime trap, since `sdiv` and `udiv` would quietly give 0.
+    fn gen_int_op(&mut self, op: &Token, signed: bool, dst: &str, rhs: &str) {
+        if matches!(op, Token::Slash | Token::Percent) {
+            self.runtime.require("__hamer_div_zero");
+            self.output.push_str(&format!("    cbz {}, __hamer_div_zero\n", rhs));
+        }
+        self.output.push_str(&int_instr(op, signed, dst, rhs));
+    }
+
+    /// `dst = dst op v`, with an immediate where the instruction takes one. Other
+    /// constants go through x7, which is never an operator's destination.
+    fn gen_int_op_imm(&mut self, op: &Token, signed: bool, dst: &str, v: i64) {
+        let imm = match op {
+            Token::Plus | Token::Minus => (0..4096).contains(&v),
+            Token::Shl | Token::Shr => (0..64).contains(&v),
+            _ => false,
+        };
+        if imm {
+            self.output.push_str(&int_instr(op, signed, dst, &format!("#{}", v)));
+        } else if v != 0 {
+            // A nonzero constant divisor can't trap.
+            self.output.push_str(&Self::load_imm("x7", v));
+            self.output.push_str(&int_instr(op, signed, dst, "x7"));
+        } else {
+            self.output.push_str(&Self::load_imm("x7", v));
+            self.gen_int_op(op, signed, dst, "x7");
+        }
+    }
+
+    /// Evaluates `e` into the d register `dst`, converting integer parts.
+    /// d17 holds the right operand, like x10 does for integers.
+    fn gen_fexpr(&mut self, e: &Expr, dst: &str) {
+        match e {
+            Expr::Number(n) => self.output.push_str(&Self::load_fimm(dst, *n)),
+            Expr::Path(path) if self.is_float_path(path) => {
+                if !self.in_reg(path) {
+                    let (ty, addr) = self.gen_addr(path);
+                    self.output.push_str(&ty.load(dst, &addr));
+                } else if let (reg, _, _) = self.get_path_info(path)
+                    && reg != dst
+                {
+                    self.output.push_str(&format!("    fmov {}, {}\n", dst, reg));
+                }
+            }
+            Expr::Neg(inner) => {
+                self.gen_fexpr(inner, dst);
+                self.output.push_str(&format!("    fneg {}, {}\n", dst, dst));
+            }
+            Expr::Format { value, .. } => self.gen_fexpr(value, dst),
+            Expr::Binary { op, lhs, rhs } => {
+                self.gen_fexpr(lhs, dst);
+                let instr = match op {
+                    Token::Minus => "fsub",
+                    Token::Star => "fmul",
+                    Token::Slash => "fdiv",
+                    _ => "fadd",
+                };
+                match rhs.as_ref() {
+                    Expr::Number(_) | Expr::Path(_) => self.gen_fexpr(rhs, "d17"),
+                    _ => {
+                        self.output.push_str(&format!("    str {}, [sp, #-16]!\n", dst));
+                        self.gen_fexpr(rhs, dst);
+                        self.output.push_str(&format!("    fmov d17, {}\n    ldr {}, [sp], #16\n", dst, dst));
+                    }
+                }
+                self.output.push_str(&format!("    {} {}, {}, d17\n", instr, dst, dst));
+            }
+            e => {
+                let conv = if self.expr_signed(e) { "scvtf" } else { "ucvtf" };
+                self.gen_expr(e, "x9");
+                self.output.push_str(&format!("    {} {}, x9\n", conv, dst));
+            }
+        }
+    }
+
+    /// Prints a float with `precision` fraction digits (6 by default) through `__hamer_print_f64`.
+    fn gen_print_float(&mut self, e: &Expr, spec: &FormatSpec, term: u8, fd: u8) {
+        let word = spec.precision.unwrap_or(6) as u64 | (spec.plus as u64) << 9 | (term as u64) << 24;
+        self.output.push_str("\n    stp x0, x1, [sp, #-16]!\n");
+        self.gen_fexpr(e, "d0");
+        self.output.push_str(&Self::load_imm("x1", word as i64));
+        self.output.push_str(&format!("    mov x2, #{}\n", fd));
+        let call = self.runtime.call("__hamer_print_f64");
+        self.output.push_str(&call);
+        self.output.push_str("    ldp x0, x1, [sp], #16\n");
+    }
+
+    /// Compares two values and returns the condition under which `lhs op rhs` holds.
+    /// Integers go through `dst` and x10 the way arithmetic does, and compare unsigned
+    /// when neither side is signed.
+    fn gen_compare(&mut self, lhs: &Expr, op: &Token, rhs: &Expr, dst: &str) -> &'static str {
+        if self.expr_float(lhs) || self.expr_float(rhs) {
+            self.gen_fexpr(lhs, "d16");
+            match rhs {
+                Expr::Number(_) | Expr::Path(_) => self.gen_fexpr(rhs, "d17"),
+                _ => {
+                    self.output.push_str("    str d16, [sp, #-16]!\n");
+                    self.gen_fexpr(rhs, "d16");
+                    self.output.push_str("    fmov d17, d16\n    ldr d16, [sp], #16\n");
+                }
+            }
+            self.output.push_str("    fcmp d16, d17\n");
+            // An unordered compare (NaN) sets C and V, so none of these hold on NaN.
+            return match op {
+                Token::Greater => "gt",
+                Token::Less => "mi",
+                _ => "eq",
+            };
+        }
+        let mut signed = self.expr_signed(lhs) || self.expr_signed(rhs);
+        if self.expr_str(lhs) || self.expr_str(rhs) {
+            // Strings compare by content; the runtime answers -1, 0 or 1.
+            self.gen_call("__hamer_str_cmp", &[lhs, rhs], dst);
+            self.output.push_str(&format!("    cmp {}, #0\n", dst));
+            signed = true;
+        } else {
+            self.gen_expr(lhs, dst);
+            match rhs {
+                Expr::Number(v) if (0.0..4096.0).contains(v) => {
+                    self.output.push_str(&format!("    cmp {}, #{}\n", dst, *v as i64));
+                }
+                Expr::Bool(b) => self.output.push_str(&format!("    cmp {}, #{}\n", dst, *b as u8)),
+                Expr::Number(v) => {
+                    self.output.push_str(&Self::load_imm("x10", *v as i64));
+                    self.output.push_str(&format!("    cmp {}, x10\n", dst));
+                }
+                Expr::Path(p) if self.reads_in_place(p, dst) => {
+                    self.gen_expr(rhs, "x10");
+                    self.output.push_str(&format!("    cmp {}, x10\n", dst));
+                }
+                _ => {
+                    self.output.push_str(&format!("    str {}, [sp, #-16]!\n", dst));
+                    self.gen_expr(rhs, dst);
+                    self.output.push_str(&format!("    mov x10, {}\n    ldr {}, [sp], #16\n    cmp {}, x10\n", dst, dst, dst));
                 }
             }
         }
-        (reg, offset)
+        match op {
+            Token::Greater if signed => "gt",
+            Token::Greater => "hi",
+            Token::Less if signed => "lt",
+            Token::Less => "lo",
+            _ => "eq",
+        }
+    }
+
+    /// Jumps to `label` unless `cond` holds. A comparison branches on its flags;
+    /// any other bool is tested against zero.
+    fn gen_branch_unless(&mut self, cond: &Expr, label: &str) {
+        match cond {
+            Expr::Binary { op, lhs, rhs } if is_compare(op) => {
+                let c = self.gen_compare(lhs, op, rhs, "x1");
+                self.output.push_str(&format!("    b.{} {}\n", invert(c), label));
+            }
+            Expr::Bool(true) => {}
+            Expr::Bool(false) => self.output.push_str(&format!("    b {}\n", label)),
+            _ => {
+                self.gen_expr(cond, "x1");
+                self.output.push_str(&format!("    cbz x1, {}\n", label));
+            }
+        }
+    }
+
+    fn gen_print_value(&mut self, e: &Expr, term: u8, fd: u8) {
+        let signed = self.expr_signed(e);
+        self.output.push_str("\n    stp x0, x1, [sp, #-16]!\n");
+        self.gen_expr(e, "x0");
+        if !self.opts.inline_runtime {
+            self.output.push_str(&format!("    mov x1, #{}\n    mov x2, #{}\n", term, fd));
+            let call = self.runtime.call(if signed { "__hamer_print_i64" } else { "__hamer_print_u64" });
+            self.output.push_str(&call);
+            self.output.push_str("    ldp x0, x1, [sp], #16\n");
+            return;
+        }
+        let id = self.output.len();
+        // Signed values print their magnitude, then a '-' is stored in front.
+        let (abs, minus) = if signed {
+            (
+                "    mov x4, x0\n    cmp x0, #0\n    cneg x0, x0, lt\n".to_string(),
+                format!("\n    tbz x4, #63, .Lps{}\n    sub x1, x1, #1\n    mov w2, #45\n    strb w2, [x1]\n.Lps{}:", id, id),
+            )
+        } else {
+            (String::new(), String::new())
+        };
+        self.output.push_str(&format!("{}    sub sp, sp, #32
+    mov x1, sp
+    add x1, x1, #31
+    mov w2, #{}
+    strb w2, [x1]
+.Lp{}:
+    sub x1, x1, #1
+    udiv x2, x0, x11
+    msub x3, x2, x11, x0
+    add x3, x3, #48
+    strb w3, [x1]
+    mov x0, x2
+    cbnz x0, .Lp{}{}
+    mov x0, #{}
+    mov x2, sp
+    add x2, x2, #32
+    sub x2, x2, x1
+    mov x8, #64
+    svc #0
+    add sp, sp, #32
+    ldp x0, x1, [sp], #16\n", abs, term, id, id, minus, fd));
+    }
+
+    /// Prints `e` under `spec` through `__hamer_format`; `term` is appended unless 0.
+    fn gen_print_formatted(&mut self, e: &Expr, spec: &FormatSpec, term: u8, fd: u8) {
+        let signed = spec.signed.unwrap_or_else(|| self.expr_signed(e));
+        let word = spec.base as u64
+            | (signed as u64) << 8
+            | (spec.plus as u64) << 9
+            | (spec.zero_pad as u64) << 10
+            | (spec.upper as u64) << 11
+            | (spec.width as u64) << 16
+            | (term as u64) << 24;
+        self.output.push_str("\n    stp x0, x1, [sp, #-16]!\n");
+        self.gen_expr(e, "x0");
+        self.output.push_str(&Self::load_imm("x1", word as i64));
+        self.output.push_str(&format!("    mov x2, #{}\n", fd));
+        let call = self.runtime.call("__hamer_format");
+        self.output.push_str(&call);
+        self.output.push_str("    ldp x0, x1, [sp], #16\n");
+    }
+
+    /// Prints one `print` item followed by `term` (0 for nothing).
+    fn gen_print_item(&mut self, item: Expr, term: u8, fd: u8) {
+        match item {
+            Expr::Format { value, spec } if !spec.is_integer() && self.expr_float(&value) => {
+                self.gen_print_float(&value, &spec, term, fd)
+            }
+            e if !matches!(e, Expr::Format { .. }) && self.expr_float(&e) => {
+                self.gen_print_float(&e, &FormatSpec::default(), term, fd)
+            }
+            Expr::Str(s) => self.gen_print_str(&s, term, fd),
+            Expr::Format { value, .. } if self.expr_str(&value) => self.gen_print_call("__hamer_str_print", &value, term, fd),
+            e if !matches!(e, Expr::Interp(_)) && self.expr_str(&e) => self.gen_print_call("__hamer_str_print", &e, term, fd),
+            Expr::Format { value, .. } if self.expr_bool(&value) => self.gen_print_call("__hamer_print_bool", &value, term, fd),
+            e if self.expr_bool(&e) => self.gen_print_call("__hamer_print_bool", &e, term, fd),
+            Expr::Interp(parts) => {
+                // Only the last part carries the separator or newline.
+                let last = parts.len().saturating_sub(1);
+                if parts.is_empty() { self.gen_print_str("", term, fd); }
+                for (i, part) in parts.into_iter().enumerate() {
+                    self.gen_print_item(part, if i == last { term } else { 0 }, fd);
+                }
+            }
+            Expr::Format { value, spec } => self.gen_print_formatted(&value, &spec, term, fd),
+            e if term == 0 => self.gen_print_formatted(&e, &FormatSpec::default(), term, fd),
+            e => self.gen_print_value(&e, term, fd),
+        }
+    }
+
+    /// Prints a string or bool computed at run time through `routine`, which takes the
+    /// value, the terminator and the fd. String literals go through `gen_print_str`.
+    fn gen_print_call(&mut self, routine: &'static str, e: &Expr, term: u8, fd: u8) {
+        self.output.push_str("\n    stp x0, x1, [sp, #-16]!\n");
+        self.gen_expr(e, "x0");
+        self.output.push_str(&format!("    mov x1, #{}\n    mov x2, #{}\n", term, fd));
+        let call = self.runtime.call(routine);
+        self.output.push_str(&call);
+        self.output.push_str("    ldp x0, x1, [sp], #16\n");
+    }
+
+    fn gen_print_str(&mut self, s: &str, term: u8, fd: u8) {
+        let term_str = match term {
+            0 => String::new(),
+            b'\n' => "\\n".to_string(),
+            c => (c as char).to_string(),
+        };
+        let len = s.len() + (term != 0) as usize;
+        if len == 0 { return; }
+        let id = self.label_count; self.label_count += 1;
+        self.output.push_str(&format!("\n.section .data\n.Lstr{}: .ascii \"{}{}\"\n.section .text\n", id, s, term_str));
+        if !self.opts.inline_runtime {
+            self.output.push_str(&format!("    adr x0, .Lstr{}\n    mov x1, #{}\n    mov x2, #{}\n", id, len, fd));
+            let call = self.runtime.call("__hamer_print_str");
+            self.output.push_str(&call);
+            return;
+        }
+        self.output.push_str(&format!("    mov x0, #{}\n    adr x1, .Lstr{}\n    mov x2, #{}\n    mov x8, #64\n    svc #0\n", fd, id, len));
     }
 
     pub fn generate(&mut self, ast: Vec<Stmt>) -> String {
+        self.uses_free = contains_free(&ast);
         for s in ast { self.gen_stmt(s); }
         self.output.push_str("\n    mov x0, #0\n    mov x8, #93\n    svc #0\n");
+        self.output.push_str(&self.runtime.emit());
         self.output.clone()
     }
 
+    /// Generates a nested block, then releases the `stack` objects it created and
+    /// the registers of the locals it declared.
+    fn gen_block(&mut self, body: Vec<Stmt>) {
+        let block = self.enter_block();
+        for s in body { self.gen_stmt(s); }
+        self.leave_block(block);
+    }
+
+    fn enter_block(&mut self) -> BlockState {
+        BlockState {
+            stack_bytes: self.stack_bytes,
+            symbols: self.symbols.clone(),
+            obj_types: self.obj_types.clone(),
+            var_types: self.var_types.clone(),
+            reg_count: self.reg_count,
+            freg_count: self.freg_count,
+            scope: std::mem::take(&mut self.scope),
+        }
+    }
+
+    fn leave_block(&mut self, b: BlockState) {
+        if self.stack_bytes > b.stack_bytes {
+            self.output.push_str(&sp_adjust("add", self.stack_bytes - b.stack_bytes));
+            self.stack_bytes = b.stack_bytes;
+        }
+        (self.symbols, self.obj_types, self.var_types, self.reg_count, self.freg_count) =
+            (b.symbols, b.obj_types, b.var_types, b.reg_count, b.freg_count);
+        self.scope = b.scope;
+    }
+
+    /// Bytes an arena saves on the stack: the heap pointer, chunk end and newest mapping,
+    /// plus the free lists when the program frees. Under `--gc` an arena is a plain block
+    /// and the collector reclaims what it allocates, so it saves nothing.
+    fn arena_size(&self) -> usize {
+        if self.opts.gc { 0 } else if self.uses_free { 1056 } else { 32 }
+    }
+
+    fn gen_arena_enter(&mut self) {
+        if self.opts.gc { return; }
+        if self.uses_free {
+            // Freed blocks may be reused inside the arena, so the free lists are saved too.
+            self.output.push_str("    sub sp, sp, #1056\n    mov x0, sp\n");
+            let call = self.runtime.call("__hamer_arena_enter");
+            self.output.push_str(&call);
+        } else {
+            self.output.push_str("    adrp x1, __hamer_heap_end\n    ldr x1, [x1, :lo12:__hamer_heap_end]\n    adrp x2, __hamer_maps\n    ldr x2, [x2, :lo12:__hamer_maps]\n    stp x20, x1, [sp, #-32]!\n    str x2, [sp, #16]\n");
+        }
+    }
+
+    fn gen_arena_leave(&mut self) {
+        if self.opts.gc { return; }
+        if self.uses_free {
+            self.output.push_str("    mov x0, sp\n");
+            let call = self.runtime.call("__hamer_arena_leave");
+            self.output.push_str(&call);
+            self.output.push_str("    add sp, sp, #1056\n");
+        } else {
+            self.output.push_str("    ldr x2, [sp, #16]\n    ldp x0, x1, [sp], #32\n");
+            let call = self.runtime.call("__hamer_arena_rewind");
+            self.output.push_str(&call);
+        }
+    }
+
+    /// Dispatches on an integer: through a jump table in `.data` when the arm values
+    /// are dense enough, through a chain of compares otherwise.
+    fn gen_match(&mut self, value: Expr, arms: Vec<MatchArm>) {
+        let id = self.label_count; self.label_count += 1;
+        let arm_label = |i: usize| format!(".Lm{}_{}", id, i);
+        let end = format!(".Lm_end{}", id);
+        let default = arms.iter().position(|a| a.values.is_empty()).map_or(end.clone(), arm_label);
+        let cases: Vec<(i64, usize)> = arms.iter().enumerate()
+            .flat_map(|(i, a)| a.values.iter().filter_map(move |v| v.const_int().map(|n| (n, i))))
+            .collect();
+        self.gen_expr(&value, "x1");
+        let lo = cases.iter().map(|c| c.0).min().unwrap_or(0);
+        let hi = cases.iter().map(|c| c.0).max().unwrap_or(0);
+        // A table pays off from four cases on, as long as at least half its slots are used.
+        // Values too far apart for that, including spans that overflow, compare in turn.
+        let slots = hi.checked_sub(lo)
+            .and_then(|d| usize::try_from(d).ok())
+            .filter(|&d| d < 2 * cases.len())
+            .map(|d| d + 1);
+        if let Some(slots) = slots.filter(|_| cases.len() >= 4) {
+            if lo != 0 { self.gen_int_op_imm(&Token::Minus, true, "x1", lo); }
+            if slots - 1 < 4096 {
+                self.output.push_str(&format!("    cmp x1, #{}\n", slots - 1));
+            } else {
+                self.output.push_str(&Self::load_imm("x2", slots as i64 - 1));
+                self.output.push_str("    cmp x1, x2\n");
+            }
+            self.output.push_str(&format!("    b.hi {}\n    adr x2, .Lm_table{}\n    ldr x2, [x2, x1, lsl #3]\n    br x2\n", default, id));
+            let mut table = vec![default.clone(); slots];
+            for &(n, i) in &cases { table[(n - lo) as usize] = arm_label(i); }
+            self.output.push_str(&format!("\n.section .data\n.balign 8\n.Lm_table{}:\n", id));
+            for target in table { self.output.push_str(&format!("    .quad {}\n", target)); }
+            self.output.push_str(".section .text\n");
+        } else {
+            for &(n, i) in &cases {
+                if (0..4096).contains(&n) {
+                    self.output.push_str(&format!("    cmp x1, #{}\n", n));
+                } else if (-4095..0).contains(&n) {
+                    self.output.push_str(&format!("    cmn x1, #{}\n", -n));
+                } else {
+                    self.output.push_str(&Self::load_imm("x2", n));
+                    self.output.push_str("    cmp x1, x2\n");
+                }
+                self.output.push_str(&format!("    b.eq {}\n", arm_label(i)));
+            }
+            self.output.push_str(&format!("    b {}\n", default));
+        }
+        let last = arms.len().saturating_sub(1);
+        for (i, arm) in arms.into_iter().enumerate() {
+            self.output.push_str(&format!("{}:\n", arm_label(i)));
+            self.gen_block(arm.body);
+            if i != last { self.output.push_str(&format!("    b {}\n", end)); }
+        }
+        self.output.push_str(&format!("{}:\n", end));
+    }
+
+    /// `break` or `continue`: releases the `stack` objects the loop body holds, leaves
+    /// the arenas opened inside it, innermost first, and jumps out or to the next round.
+    fn gen_loop_exit(&mut self, label: Option<&str>, brk: bool) {
+        let Some(l) = self.loops.iter().rposition(|l| label.is_none_or(|n| l.label.as_deref() == Some(n))) else { return };
+        let (id, next, bytes, arenas) = (self.loops[l].id, self.loops[l].next.clone(), self.loops[l].stack_bytes, self.loops[l].arenas);
+        let mut depth = self.stack_bytes;
+        for i in (arenas..self.arenas.len()).rev() {
+            let saved_at = self.arenas[i] + self.arena_size();
+            if depth > saved_at { self.output.push_str(&sp_adjust("add", depth - saved_at)); }
+            self.gen_arena_leave();
+            depth = self.arenas[i];
+        }
+        if depth > bytes { self.output.push_str(&sp_adjust("add", depth - bytes)); }
+        let target = if brk { format!(".Lw_end{}", id) } else { next };
+        self.output.push_str(&format!("    b {}\n", target));
+    }
+
+    /// Adds the layout of an array type like `[64]u64` the first time it's allocated,
+    /// with its collector descriptor under `--gc`.
+    fn define_array(&mut self, class: &str) {
+        if self.class_map.contains_key(class) { return; }
+        let Some((elem, len)) = layout::array_type(class) else { return };
+        let layout = ClassLayout::array(FieldType::parse(elem).unwrap_or(FieldType::Any), len);
+        if self.opts.gc {
+            let (words, bitmap) = layout.ref_bitmap();
+            self.output.push_str(&format!("\n.section .data\n.balign 8\n{}: .quad {}, {}\n.section .text\n", type_label(class), words, bitmap));
+        }
+        self.class_map.insert(class.to_string(), layout);
+    }
+
+    /// The register for a local declared in the current block: a d register for a float.
+    fn declare(&mut self, name: &str, float: bool, span: Span) -> String {
+        let kind_changed = self.symbols.get(name).is_some_and(|r| r.starts_with('d') != float);
+        if self.scope.insert(name.to_string()) || kind_changed {
+            let reg = if float {
+                if self.freg_count >= 16 {
+                    self.errors.push(format!("{}: no register left for `{}`; at most 8 f64 locals can be in scope at once", span, name));
+                }
+                self.freg_count += 1;
+                format!("d{}", self.freg_count - 1)
+            } else {
+                if self.reg_count == 20 { self.reg_count = 21; }
+                if self.reg_count >= 29 {
+                    self.errors.push(format!("{}: no register left for `{}`; at most 16 locals can be in scope at once", span, name));
+                }
+                self.reg_count += 1;
+                format!("x{}", self.reg_count - 1)
+            };
+            self.symbols.insert(name.to_string(), reg);
+            self.var_types.remove(name);
+        }
+        self.obj_types.remove(name);
+        self.symbols[name].clone()
+    }
+
     fn gen_stmt(&mut self, stmt: Stmt) {
         match stmt {
-            Stmt::MergeBlock(content) => {
-                let mut lexer = Lexer::new(content);
-                let mut tokens = Vec::new();
-                loop {
-                    let t = lexer.next_token();
-                    if t == Token::EOF { break; }
-                    tokens.push(t);
-                }
-                let mut parser = Parser::new(tokens);
-                let sub_ast = parser.parse_program();
+            Stmt::MergeBlock(sub_ast) => {
                 for s in sub_ast { self.gen_stmt(s); }
             }
             Stmt::PythonBlock(script) => {
@@ -68,126 +876,399 @@ impl Generator {
                 self.output.push_str(&format!("    {}\n", code));
                 self.output.push_str("    .att_syntax\n");
             }
-            Stmt::AsmBlock(code) => { self.output.push_str(&format!("    {}\n", code)); }
-            Stmt::ProbIf { chance, body } => {
+            Stmt::AsmBlock(code) if code.is_empty() => {}
+            // The markers keep the peephole rules off user assembly.
+            Stmt::AsmBlock(code) => { self.output.push_str(&format!("    {}\n    {}\n    {}\n", peephole::ASM_START, code, peephole::ASM_END)); }
+            Stmt::Const { .. } | Stmt::EnumDef { .. } => {}
+            Stmt::GlobalDef { name, ty, value, export, .. } => {
+                let ty = match (ty, &value) {
+                    (Some(t), _) => FieldType::parse(&t).unwrap_or(FieldType::I64),
+                    (None, Some(Expr::Number(n))) if n.fract() != 0.0 => FieldType::F64,
+                    (None, Some(Expr::Bool(_))) => FieldType::Bool,
+                    _ => FieldType::I64,
+                };
+                let (init, bits) = match value {
+                    Some(Expr::Number(n)) if ty == FieldType::F64 => (format!("{:?}", n), n.to_bits()),
+                    Some(Expr::Number(n)) => ((n as i64).to_string(), n as i64 as u64),
+                    Some(Expr::Bool(b)) => ((b as u8).to_string(), b as u64),
+                    _ => ("0".into(), 0),
+                };
+                let size = ty.size();
+                let sym = global_symbol(&name);
+                // The program's own code uses the prefixed symbol; an export also gets its plain name.
+                let global = if export { format!(".global {}\n.set {}, {}\n", name, name, sym) } else { String::new() };
+                // Globals that start at zero take no space in the file.
+                let slot = if bits == 0 {
+                    format!(".section .bss\n.balign {}\n{}: .skip {}\n{}", size, sym, size, global)
+                } else {
+                    let dir = match size {
+                        _ if ty == FieldType::F64 => ".double",
+                        1 => ".byte",
+                        2 => ".hword",
+                        4 => ".word",
+                        _ => ".quad",
+                    };
+                    format!(".section .data\n.balign {}\n{}: {} {}\n{}", size, sym, dir, init, global)
+                };
+                self.output.push_str(&format!("\n{}.section .text\n", slot));
+                self.globals.insert(name, ty);
+            }
+            Stmt::ProbIf { chance, body, .. } => {
                 let id = self.label_count; self.label_count += 1;
                 let math_reg = self.symbols.get("math").cloned().unwrap_or("x12".into());
+                if !self.opts.inline_runtime {
+                    self.output.push_str(&format!("\n    // Chaos Roll {}%\n    mov x0, {}\n", chance, math_reg));
+                    let call = self.runtime.call("__hamer_roll");
+                    self.output.push_str(&call);
+                    self.output.push_str(&format!("    cmp x0, #{}\n    b.hs .Lif{}\n", chance as i64, id));
+                    self.gen_block(body);
+                    self.output.push_str(&format!(".Lif{}:\n", id));
+                    return;
+                }
                 self.output.push_str(&format!("\n    // Chaos Roll {}%\n    ldr x1, [{}, #8]\n", chance, math_reg));
                 self.output.push_str(&format!("    cmp x1, #0\n    b.ne .Lskp{}\n    mrs x1, cntvct_el0\n.Lskp{}:\n", id, id));
                 self.output.push_str("    ldr x2, =0x9E3779B97F4A7C15\n    mul x1, x1, x2\n    eor x1, x1, x1, lsr #33\n");
                 self.output.push_str(&format!("    str x1, [{}, #8]\n", math_reg));
                 self.output.push_str("    and x1, x1, #0x7FFFFFFF\n    mov x2, #100\n    udiv x3, x1, x2\n    msub x1, x3, x2, x1\n");
                 self.output.push_str(&format!("    cmp x1, #{}\n    b.hs .Lif{}\n", chance as i64, id));
-                for s in body { self.gen_stmt(s); }
+                self.gen_block(body);
                 self.output.push_str(&format!(".Lif{}:\n", id));
             }
-            Stmt::IfStmt { path, op, rhs_val, body } => {
+            Stmt::IfStmt { cond, body, .. } => {
                 let id = self.label_count; self.label_count += 1;
-                let (reg, offset) = self.get_path_info(&path);
-                if path.len() > 1 {
-                    self.output.push_str(&format!("    ldr x1, [{}, #{}]\n", reg, offset));
-                } else {
-                    self.output.push_str(&format!("    mov x1, {}\n", reg));
-                }
-                let cond = match op {
-                    Token::Equal => "ne",
-                    Token::Greater => "le",
-                    Token::Less => "ge",
-                    _ => "eq",
-                };
-                self.output.push_str(&format!("    cmp x1, #{}\n    b.{} .Lif{}\n", rhs_val as i64, cond, id));
-                for s in body { self.gen_stmt(s); }
+                self.gen_branch_unless(&cond, &format!(".Lif{}", id));
+                self.gen_block(body);
                 self.output.push_str(&format!(".Lif{}:\n", id));
             }
-            Stmt::WhileStmt { path, op, rhs_val, body } => {
+            Stmt::WhileStmt { cond, body, label, .. } => {
                 let id = self.label_count; self.label_count += 1;
                 self.output.push_str(&format!(".Lw_start{}:\n", id));
-                let (reg, offset) = self.get_path_info(&path);
-                if path.len() > 1 {
-                    self.output.push_str(&format!("    ldr x1, [{}, #{}]\n", reg, offset));
-                } else {
-                    self.output.push_str(&format!("    mov x1, {}\n", reg));
+                self.gen_branch_unless(&cond, &format!(".Lw_end{}", id));
+                let next = format!(".Lw_start{}", id);
+                self.loops.push(Loop { label, id, next, stack_bytes: self.stack_bytes, arenas: self.arenas.len() });
+                self.gen_block(body);
+                self.loops.pop();
+                self.output.push_str(&format!("    b .Lw_start{}\n.Lw_end{}:\n", id, id));
+            }
+            Stmt::ForStmt { var, start, end, step, body, label, span } => {
+                let id = self.label_count; self.label_count += 1;
+                // `start` is read before the loop variable exists, so `for i in i..n` sees the outer `i`.
+                self.gen_expr(&start, "x1");
+                let block = self.enter_block();
+                let reg = self.declare(&var, false, span);
+                self.output.push_str(&format!("    mov {}, x1\n.Lw_start{}:\n", reg, id));
+                let i = Path { names: vec![var], index: None, span };
+                let test = |op| Expr::Binary { op, lhs: Box::new(Expr::Path(i.clone())), rhs: Box::new(end.clone()) };
+                // A negative step counts down to `end` instead of up to it. Constant steps are
+                // folded to a number by now; a computed one is checked every round, like `end`.
+                match &step {
+                    None | Some(Expr::Number(_)) => {
+                        let down = matches!(step, Some(Expr::Number(n)) if n < 0.0);
+                        self.gen_branch_unless(&test(if down { Token::Greater } else { Token::Less }), &format!(".Lw_end{}", id));
+                    }
+                    Some(step) => {
+                        self.gen_expr(step, "x1");
+                        self.output.push_str(&format!("    tbnz x1, #63, .Lf_down{}
+", id));
+                        self.gen_branch_unless(&test(Token::Less), &format!(".Lw_end{}", id));
+                        self.output.push_str(&format!("    b .Lf_body{}
+.Lf_down{}:
+", id, id));
+                        self.gen_branch_unless(&test(Token::Greater), &format!(".Lw_end{}", id));
+                        self.output.push_str(&format!(".Lf_body{}:
+", id));
+                    }
                 }
-                let cond = match op {
-                    Token::Equal => "ne",
-                    Token::Greater => "le",
-                    Token::Less => "ge",
-                    _ => "eq",
-                };
-                self.output.push_str(&format!("    cmp x1, #{}\n    b.{} .Lw_end{}\n", rhs_val as i64, cond, id));
-                for s in body { self.gen_stmt(s); }
+                let next = format!(".Lw_next{}", id);
+                self.loops.push(Loop { label, id, next, stack_bytes: self.stack_bytes, arenas: self.arenas.len() });
+                self.gen_block(body);
+                self.loops.pop();
+                self.output.push_str(&format!(".Lw_next{}:\n", id));
+                self.gen_stmt(Stmt::FieldMath { path: i, op: Token::Plus, rhs: step.unwrap_or(Expr::Number(1.0)) });
                 self.output.push_str(&format!("    b .Lw_start{}\n.Lw_end{}:\n", id, id));
+                self.leave_block(block);
             }
-            Stmt::LocalAssign { name, value } => {
-                let reg = self.symbols.entry(name.clone()).or_insert_with(|| {
-                    let r = format!("x{}", self.reg_count); self.reg_count += 1; r
-                }).clone();
-                self.output.push_str(&format!("    mov {}, #{}\n", reg, value as i64));
+            Stmt::Match { value, arms, .. } => self.gen_match(value, arms),
+            Stmt::Break { label, .. } => self.gen_loop_exit(label.as_deref(), true),
+            Stmt::Continue { label, .. } => self.gen_loop_exit(label.as_deref(), false),
+            Stmt::Exit { status, .. } => {
+                self.gen_expr(&status, "x0");
+                self.output.push_str("    mov x8, #93\n    svc #0\n");
+            }
+            Stmt::LocalAssign { name, ty, value, span, .. } => {
+                let float = ty.as_deref().map_or(self.expr_float(&value), |t| t == "f64");
+                // Untyped locals take the kind of their value.
+                let kind = ty.or_else(|| {
+                    if self.expr_str(&value) { Some("str".into()) } else if self.expr_bool(&value) { Some("bool".into()) } else { None }
+                });
+                let class = match &value {
+                    Expr::Path(p) if p.is_local() => self.obj_types.get(&p[0]).cloned(),
+                    _ => None,
+                };
+                // Evaluate before declaring so `local n = n + 1` reads the outer `n`.
+                let tmp = if float { "d16" } else { "x1" };
+                match value {
+                    Expr::Number(_) => {}
+                    ref e if float => self.gen_fexpr(e, tmp),
+                    ref e => self.gen_expr(e, tmp),
+                }
+                let reg = self.declare(&name, float, span);
+                match value {
+                    Expr::Number(v) if float => self.output.push_str(&Self::load_fimm(&reg, v)),
+                    Expr::Number(v) => self.output.push_str(&Self::load_imm(&reg, v as i64)),
+                    _ => self.output.push_str(&format!("    {} {}, {}\n", if float { "fmov" } else { "mov" }, reg, tmp)),
+                }
+                match kind {
+                    Some(t) => { self.var_types.insert(name.clone(), t); }
+                    None => { self.var_types.remove(&name); }
+                }
+                // The type checker keeps literals in range.
+                if !matches!(value, Expr::Number(_)) { self.narrow_local(&name); }
+                if let Some(class) = class { self.obj_types.insert(name, class); }
             }
             Stmt::FieldAssign { path, value } => {
-                let (reg, offset) = self.get_path_info(&path);
-                if path.len() > 1 {
-                    self.output.push_str(&format!("    mov x1, #{}\n    str x1, [{}, #{}]\n", value as i64, reg, offset));
+                let float = self.is_float_path(&path);
+                let (reg, _, _) = self.get_path_info(&path);
+                if self.in_reg(&path)
+                    && let Expr::Number(v) = value
+                {
+                    let code = if float { Self::load_fimm(&reg, v) } else { Self::load_imm(&reg, v as i64) };
+                    self.output.push_str(&code);
+                    return;
+                }
+                // Evaluate aside so `x = y - x` still reads the old `x`.
+                let tmp = if float { "d16" } else { "x1" };
+                if float { self.gen_fexpr(&value, tmp) } else { self.gen_expr(&value, tmp) }
+                if self.in_reg(&path) {
+                    self.output.push_str(&format!("    {} {}, {}\n", if float { "fmov" } else { "mov" }, reg, tmp));
+                    self.narrow_local(&path[0]);
                 } else {
-                    self.output.push_str(&format!("    mov {}, #{}\n", reg, value as i64));
+                    let (ty, addr) = self.gen_addr(&path);
+                    self.output.push_str(&ty.store(tmp, &addr));
                 }
             }
-            Stmt::FieldMath { path, op, rhs_val } => {
-                let (reg, offset) = self.get_path_info(&path);
-                let instr = match op {
-                    Token::Plus => "add",
-                    Token::Minus => "sub",
-                    _ => "add",
+            Stmt::FieldMath { path, op, rhs } if self.expr_str(&Expr::Path(path.clone())) => {
+                // Appending builds a new string, so it's an ordinary assignment.
+                let value = Expr::Binary { op, lhs: Box::new(Expr::Path(path.clone())), rhs: Box::new(rhs) };
+                self.gen_stmt(Stmt::FieldAssign { path, value });
+            }
+            Stmt::FieldMath { path, op, rhs } => {
+                // One load and one store of the target, through an address computed once.
+                let (reg, _, _) = self.get_path_info(&path);
+                if self.is_float_path(&path) {
+                    let instr = match op {
+                        Token::Minus => "fsub",
+                        Token::Star => "fmul",
+                        Token::Slash => "fdiv",
+                        _ => "fadd",
+                    };
+                    // d18 holds the right-hand side, which may use d16 and d17 on the way.
+                    self.gen_fexpr(&rhs, "d18");
+                    if self.in_reg(&path) {
+                        self.output.push_str(&format!("    {} {}, {}, d18\n", instr, reg, reg));
+                        return;
+                    }
+                    let (ty, addr) = self.gen_addr(&path);
+                    self.output.push_str(&ty.load("d16", &addr));
+                    self.output.push_str(&format!("    {} d16, d16, d18\n", instr));
+                    self.output.push_str(&ty.store("d16", &addr));
+                    return;
+                }
+                let signed = self.expr_signed(&Expr::Path(path.clone()));
+                let imm = match rhs {
+                    Expr::Number(v) => Some(v as i64),
+                    rhs => {
+                        // x4 holds the right-hand side; evaluating it may use x1.
+                        self.gen_expr(&rhs, "x4");
+                        None
+                    }
                 };
-                if path.len() > 1 {
-                    self.output.push_str(&format!("    ldr x1, [{}, #{}]\n    {} x1, x1, #{}\n    str x1, [{}, #{}]\n", reg, offset, instr, rhs_val as i64, reg, offset));
+                let mut slot = None;
+                let target = if self.in_reg(&path) {
+                    reg
                 } else {
-                    self.output.push_str(&format!("    {} {}, {}, #{}\n", instr, reg, reg, rhs_val as i64));
+                    let (ty, addr) = self.gen_addr(&path);
+                    self.output.push_str(&ty.load("x1", &addr));
+                    slot = Some((ty, addr));
+                    "x1".to_string()
+                };
+                match imm {
+                    Some(v) => self.gen_int_op_imm(&op, signed, &target, v),
+                    None => self.gen_int_op(&op, signed, &target, "x4"),
+                }
+                match slot {
+                    Some((ty, addr)) => self.output.push_str(&ty.store("x1", &addr)),
+                    None => self.narrow_local(&path[0]),
                 }
             }
-            Stmt::PrintVar(name) => {
-                if let Some(reg) = self.symbols.get(&name).cloned() {
-                    let id = self.output.len();
-                    self.output.push_str(&format!("
-    stp x0, x1, [sp, #-16]!
-    mov x0, {}
-    sub sp, sp, #32
-    mov x1, sp
-    add x1, x1, #31
-    mov w2, #10
-    strb w2, [x1]
-.Lp{}:
-    sub x1, x1, #1
-    udiv x2, x0, x11
-    msub x3, x2, x11, x0
-    add x3, x3, #48
-    strb w3, [x1]
-    mov x0, x2
-    cbnz x0, .Lp{}
-    mov x0, #1
-    mov x2, sp
-    add x2, x2, #32
-    sub x2, x2, x1
-    mov x8, #64
-    svc #0
-    add sp, sp, #32
-    ldp x0, x1, [sp], #16\n", reg, id, id));
+            Stmt::Print { items, fd, newline, .. } => {
+                // Items are separated by a space; `print` and `eprint` end the line.
+                let last = items.len().saturating_sub(1);
+                for (i, item) in items.into_iter().enumerate() {
+                    let term = if i < last { b' ' } else if newline { b'\n' } else { 0 };
+                    self.gen_print_item(item, term, fd);
                 }
             }
-            Stmt::PrintString(s) => {
-                let id = self.label_count; self.label_count += 1;
-                self.output.push_str(&format!("\n.section .data\n.Lstr{}: .ascii \"{}\\n\"\n.section .text\n", id, s));
-                self.output.push_str(&format!("    mov x0, #1\n    adr x1, .Lstr{}\n    mov x2, #{}\n    mov x8, #64\n    svc #0\n", id, s.len() + 1));
+            Stmt::Free { value: e, .. } => {
+                // Under --gc the collector owns every object, so `free` only drops the reference.
+                if !self.opts.gc {
+                    self.gen_expr(&e, "x0");
+                    let call = self.runtime.call("__hamer_free");
+                    self.output.push_str(&call);
+                }
+                // Don't leave a dangling pointer in the local.
+                if let Expr::Path(path) = &e
+                    && path.len() == 1
+                    && let Some(reg) = self.symbols.get(&path[0])
+                {
+                    self.output.push_str(&format!("    mov {}, #0\n", reg));
+                }
+            }
+            Stmt::ClassDef { name, fields, packed, .. } => {
+                let layout = ClassLayout::new(&fields, packed);
+                if self.opts.gc && !self.class_map.contains_key(&name) {
+                    // Type descriptor: size in words, then a bitmap of the words that may hold references.
+                    let (words, bitmap) = layout.ref_bitmap();
+                    self.output.push_str(&format!("\n.section .data\n.balign 8\n{}: .quad {}, {}\n.section .text\n", type_label(&name), words, bitmap));
+                }
+                self.class_map.insert(name, layout);
+            }
+            Stmt::StackAlloc { var_name, class_name, span } => {
+                let reg = self.declare(&var_name, false, span);
+                self.define_array(&class_name);
+                self.obj_types.insert(var_name, class_name.clone());
+                if let Some(f) = self.class_map.get(&class_name) {
+                    // Keep sp 16-byte aligned and start the fields at zero like a fresh heap object.
+                    let size = f.size.div_ceil(16).max(1) * 16;
+                    self.stack_bytes += size;
+                    self.output.push_str(&sp_adjust("sub", size));
+                    self.output.push_str(&format!("    mov {}, sp\n    mov x1, sp\n", reg));
+                    if size <= 128 {
+                        for _ in 0..size / 16 { self.output.push_str("    stp xzr, xzr, [x1], #16\n"); }
+                    } else {
+                        let id = self.label_count; self.label_count += 1;
+                        self.output.push_str(&Self::load_imm("x2", (size / 16) as i64));
+                        self.output.push_str(&format!(".Lzero{}:\n    stp xzr, xzr, [x1], #16\n    subs x2, x2, #1\n    b.ne .Lzero{}\n", id, id));
+                    }
+                }
+            }
+            Stmt::Arena(body) => {
+                let size = self.arena_size();
+                self.gen_arena_enter();
+                self.arenas.push(self.stack_bytes);
+                self.stack_bytes += size;
+                self.gen_block(body);
+                self.stack_bytes -= size;
+                self.arenas.pop();
+                self.gen_arena_leave();
             }
-            Stmt::ClassDef { name, fields } => { self.class_map.insert(name, fields); }
-            Stmt::HeapAlloc { var_name, class_name } => {
-                let reg = format!("x{}", self.reg_count); self.reg_count += 1;
-                self.symbols.insert(var_name.clone(), reg.clone());
+            Stmt::HeapAlloc { var_name, class_name, span } => {
+                let reg = self.declare(&var_name, false, span);
+                self.define_array(&class_name);
                 self.obj_types.insert(var_name, class_name.clone());
                 if let Some(f) = self.class_map.get(&class_name) {
-                    self.output.push_str(&format!("    mov {}, x20\n    add x20, x20, #{}\n", reg, f.len() * 8));
+                    if self.opts.gc {
+                        self.output.push_str(&Self::load_imm("x0", f.size as i64));
+                        self.output.push_str(&format!("    adr x1, {}\n", type_label(&class_name)));
+                        let call = self.runtime.call("__hamer_gc_alloc");
+                        self.output.push_str(&call);
+                        self.output.push_str(&format!("    mov {}, x0\n", reg));
+                        return;
+                    }
+                    if !self.opts.inline_runtime || self.uses_free {
+                        self.output.push_str(&Self::load_imm("x0", f.size as i64));
+                        let call = self.runtime.call("__hamer_alloc");
+                        self.output.push_str(&call);
+                        self.output.push_str(&format!("    mov {}, x0\n", reg));
+                        return;
+                    }
+                    // Bump inline while the chunk has room; the runtime maps a new one when it doesn't.
+                    // The block's size goes in the 8-byte header just before the object.
+                    let id = self.label_count; self.label_count += 1;
+                    let size = f.size.next_multiple_of(8).max(8);
+                    if size + 8 < 4096 {
+                        self.output.push_str(&format!("    add x1, x20, #{}\n", size + 8));
+                    } else {
+                        self.output.push_str(&format!("{}    add x1, x20, x1\n", Self::load_imm("x1", (size + 8) as i64)));
+                    }
+                    self.output.push_str(&format!("    adrp x2, __hamer_heap_end\n    ldr x2, [x2, :lo12:__hamer_heap_end]\n    cmp x1, x2\n    b.hi .Lgrow{}\n{}    str x2, [x20]\n    add {}, x20, #8\n    mov x20, x1\n    b .Lalloc{}\n.Lgrow{}:\n{}", id, Self::load_imm("x2", size as i64), reg, id, id, Self::load_imm("x0", size as i64)));
+                    let call = self.runtime.call("__hamer_alloc");
+                    self.output.push_str(&call);
+                    self.output.push_str(&format!("    mov {}, x0\n.Lalloc{}:\n", reg, id));
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// The condition that holds exactly when `cond` doesn't.
+fn invert(cond: &str) -> &'static str {
+    match cond {
+        "eq" => "ne",
+        "ne" => "eq",
+        "gt" => "le",
+        "le" => "gt",
+        "lt" => "ge",
+        "ge" => "lt",
+        "hi" => "ls",
+        "ls" => "hi",
+        "lo" => "hs",
+        "hs" => "lo",
+        "mi" => "pl",
+        _ => "mi",
+    }
+}
+
+/// The instruction for `dst = dst op rhs`. `%` divides into x8 and multiplies back;
+/// `>>` shifts in the sign bit when `signed`.
+fn int_instr(op: &Token, signed: bool, dst: &str, rhs: &str) -> String {
+    let div = if signed { "sdiv" } else { "udiv" };
+    let instr = match op {
+        Token::Minus => "sub",
+        Token::Star => "mul",
+        Token::Slash => div,
+        Token::Percent => {
+            return format!("    {} x8, {}, {}\n    msub {}, x8, {}, {}\n", div, dst, rhs, dst, rhs, dst);
+        }
+        Token::Amp => "and",
+        Token::Pipe => "orr",
+        Token::Caret => "eor",
+        Token::Shl => "lsl",
+        Token::Shr if signed => "asr",
+        Token::Shr => "lsr",
+        _ => "add",
+    };
+    format!("    {} {}, {}, {}\n", instr, dst, dst, rhs)
+}
+
+/// `add sp, sp, #bytes` or `sub`, going through x9 when the immediate doesn't encode.
+fn sp_adjust(op: &str, bytes: usize) -> String {
+    if bytes < 4096 {
+        format!("    {} sp, sp, #{}\n", op, bytes)
+    } else {
+        format!("{}    {} sp, sp, x9\n", Generator::load_imm("x9", bytes as i64), op)
+    }
+}
+
+/// The symbol of a global; the prefix keeps names like `x1` or `b` from reading as a
+/// register or a mnemonic.
+fn global_symbol(name: &str) -> String {
+    format!("hamer_g_{}", name)
+}
+
+/// The label of a class's collector descriptor; `[64]u64` becomes `.Ltype_arr64_u64`.
+fn type_label(class: &str) -> String {
+    format!(".Ltype_{}", class.replace('[', "arr").replace(']', "_"))
+}
+
+fn contains_free(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|s| match s {
+        Stmt::Free { .. } => true,
+        Stmt::IfStmt { body, .. } | Stmt::WhileStmt { body, .. } | Stmt::ForStmt { body, .. } | Stmt::ProbIf { body, .. }
+        | Stmt::Arena(body) | Stmt::MergeBlock(body) => {
+            contains_free(body)
+        }
+        Stmt::Match { arms, .. } => arms.iter().any(|a| contains_free(&a.body)),
+        _ => false,
+    })
+}
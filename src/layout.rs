@@ -0,0 +1,173 @@
+use crate::parser::Field;
+
+/// Storage type of a class field. Untyped fields are `Any`: an 8-byte signed
+/// slot that may also hold an object reference. `Str` holds a string reference,
+/// and `Bool` is a byte that is 0 or 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldType { Any, U8, U16, U32, U64, I8, I16, I32, I64, F64, Ptr, Str, Bool }
+
+impl FieldType {
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "u8" => FieldType::U8,
+            "u16" => FieldType::U16,
+            "u32" => FieldType::U32,
+            "u64" => FieldType::U64,
+            "i8" => FieldType::I8,
+            "i16" => FieldType::I16,
+            "i32" => FieldType::I32,
+            "i64" => FieldType::I64,
+            "f64" => FieldType::F64,
+            "ptr" => FieldType::Ptr,
+            "str" => FieldType::Str,
+            "bool" => FieldType::Bool,
+            _ => return None,
+        })
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            FieldType::U8 | FieldType::I8 | FieldType::Bool => 1,
+            FieldType::U16 | FieldType::I16 => 2,
+            FieldType::U32 | FieldType::I32 => 4,
+            _ => 8,
+        }
+    }
+
+    pub fn signed(self) -> bool {
+        matches!(self, FieldType::Any | FieldType::I8 | FieldType::I16 | FieldType::I32 | FieldType::I64)
+    }
+
+    /// Whether the collector has to treat the field as a possible reference.
+    pub fn may_ref(self) -> bool {
+        matches!(self, FieldType::Any | FieldType::Ptr | FieldType::Str)
+    }
+
+    /// Loads the value at `addr`, an address operand like `[x12, #8]`, into the 64-bit
+    /// register `dst`, zero- or sign-extending narrow types. `f64` loads into d registers the same way.
+    pub fn load(self, dst: &str, addr: &str) -> String {
+        let w = w_reg(dst);
+        match self {
+            FieldType::U8 | FieldType::Bool => format!("    ldrb {}, {}\n", w, addr),
+            FieldType::I8 => format!("    ldrsb {}, {}\n", dst, addr),
+            FieldType::U16 => format!("    ldrh {}, {}\n", w, addr),
+            FieldType::I16 => format!("    ldrsh {}, {}\n", dst, addr),
+            FieldType::U32 => format!("    ldr {}, {}\n", w, addr),
+            FieldType::I32 => format!("    ldrsw {}, {}\n", dst, addr),
+            _ => format!("    ldr {}, {}\n", dst, addr),
+        }
+    }
+
+    /// Stores the low bytes of `src` at `addr`.
+    pub fn store(self, src: &str, addr: &str) -> String {
+        let w = w_reg(src);
+        match self.size() {
+            1 => format!("    strb {}, {}\n", w, addr),
+            2 => format!("    strh {}, {}\n", w, addr),
+            4 => format!("    str {}, {}\n", w, addr),
+            _ => format!("    str {}, {}\n", src, addr),
+        }
+    }
+
+    /// Truncates the 64-bit register `reg` to this type's width and extends it back,
+    /// so a local of the type wraps like a field would. Empty for 64-bit types.
+    pub fn narrow(self, reg: &str) -> String {
+        let w = w_reg(reg);
+        match self {
+            FieldType::U8 => format!("    and {}, {}, #0xff\n", w, w),
+            FieldType::U16 => format!("    and {}, {}, #0xffff\n", w, w),
+            FieldType::U32 => format!("    mov {}, {}\n", w, w),
+            FieldType::I8 => format!("    sxtb {}, {}\n", reg, w),
+            FieldType::I16 => format!("    sxth {}, {}\n", reg, w),
+            FieldType::I32 => format!("    sxtw {}, {}\n", reg, w),
+            _ => String::new(),
+        }
+    }
+
+    /// The address operand of element `index` (a register) in an array of this type at `base`.
+    pub fn element(self, base: &str, index: &str) -> String {
+        match self.size() {
+            1 => format!("[{}, {}]", base, index),
+            n => format!("[{}, {}, lsl #{}]", base, index, n.trailing_zeros()),
+        }
+    }
+}
+
+/// Splits an array type name like `[64]u64` into its element type name and length.
+pub fn array_type(name: &str) -> Option<(&str, usize)> {
+    let (len, elem) = name.strip_prefix('[')?.split_once(']')?;
+    Some((elem, len.parse().ok()?))
+}
+
+fn w_reg(x: &str) -> String {
+    x.replacen('x', "w", 1)
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldLayout {
+    pub name: String,
+    pub ty: FieldType,
+    pub offset: usize,
+    /// Element count of an array field; `ty` is then the element type.
+    pub len: Option<usize>,
+}
+
+impl FieldLayout {
+    /// The offset just past the field's last byte.
+    pub fn end(&self) -> usize {
+        self.offset + self.ty.size() * self.len.unwrap_or(1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassLayout {
+    pub fields: Vec<FieldLayout>,
+    pub size: usize,
+}
+
+impl ClassLayout {
+    /// Lays fields out in order at their natural alignment, or back to back when
+    /// `packed`. An explicit `@offset` places a field exactly and later fields follow it.
+    pub fn new(fields: &[Field], packed: bool) -> Self {
+        let mut out = Vec::new();
+        let mut cursor: usize = 0;
+        let mut size = 0;
+        let mut align = 1;
+        for f in fields {
+            let ty = f.ty.as_deref().and_then(FieldType::parse).unwrap_or(FieldType::Any);
+            let natural = if packed { 1 } else { ty.size() };
+            let offset = f.offset.unwrap_or(cursor.next_multiple_of(natural));
+            align = align.max(natural);
+            let field = FieldLayout { name: f.name.clone(), ty, offset, len: f.len };
+            cursor = field.end();
+            size = size.max(cursor);
+            out.push(field);
+        }
+        Self { fields: out, size: size.next_multiple_of(align) }
+    }
+
+    /// The layout of an array object: a single unnamed array field at offset 0.
+    pub fn array(elem: FieldType, len: usize) -> Self {
+        Self {
+            fields: vec![FieldLayout { name: String::new(), ty: elem, offset: 0, len: Some(len) }],
+            size: elem.size() * len,
+        }
+    }
+
+    pub fn field(&self, name: &str) -> Option<&FieldLayout> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    /// Word count and a bitmap with one bit per 8-byte word that may hold a reference,
+    /// as the collector's type descriptor wants them. Words from 63 on share the top bit.
+    /// Under `--gc` the type checker rejects reference fields that don't start a word.
+    pub fn ref_bitmap(&self) -> (usize, u64) {
+        let mut bitmap = 0u64;
+        for f in self.fields.iter().filter(|f| f.ty.may_ref()) {
+            for word in f.offset / 8..f.offset / 8 + f.len.unwrap_or(1) {
+                bitmap |= 1 << word.min(63);
+            }
+        }
+        (self.size.div_ceil(8), bitmap)
+    }
+}
@@ -1,12 +1,28 @@
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Class, Is, Done, Local, Print, Get, At, Assign, Dot, New,
-    If, Then, While, Do, Greater, Less, Equal,
-    Plus, Minus, Star, Slash, Comma, Rest,
-    Quest, Percent, LeftBracket, RightBracket,
+    Class, Is, Done, Local, Free, Print, Write, EPrint, Get, At, Assign, Dot, New, Stack, Arena,
+    If, Then, While, Do, Greater, Less, Equal, True, False, For, In, Break, Continue, DotDot, Match, Arrow, Const, Enum, Global, Export, Exit,
+    Plus, Minus, Star, Slash, Amp, Pipe, Caret, Shl, Shr, Comma, Colon, Rest,
+    Quest, Percent, LeftBracket, RightBracket, LeftParen, RightParen,
+    /// A compound assignment such as `+=` or `<<=`, holding its operator.
+    OpAssign(Box<Token>),
     Identifier(String), Number(f64), StringLit(String), EOF,
 }
 
+/// Where a token starts in the source, 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
 pub struct Lexer {
     input: Vec<char>,
     pos: usize,
@@ -17,6 +33,30 @@ impl Lexer {
         Self { input: input.chars().collect(), pos: 0 } 
     }
 
+    pub fn tokenize(&mut self) -> Vec<Token> {
+        self.tokenize_spanned().0
+    }
+
+    /// Tokenizes and records where each token starts.
+    pub fn tokenize_spanned(&mut self) -> (Vec<Token>, Vec<Span>) {
+        let mut tokens = Vec::new();
+        let mut spans = Vec::new();
+        let mut here = Span { line: 1, col: 1 };
+        let mut scanned = 0;
+        loop {
+            self.skip_whitespace();
+            for &c in &self.input[scanned..self.pos] {
+                if c == '\n' { here = Span { line: here.line + 1, col: 1 }; } else { here.col += 1; }
+            }
+            scanned = self.pos;
+            let token = self.next_token();
+            if token == Token::EOF { break; }
+            tokens.push(token);
+            spans.push(here);
+        }
+        (tokens, spans)
+    }
+
     pub fn next_token(&mut self) -> Token {
         loop {
             self.skip_whitespace();
@@ -25,22 +65,43 @@ impl Lexer {
             let ch = self.input[self.pos];
             match ch {
                 '?' => { self.pos += 1; return Token::Quest },
-                '%' => { self.pos += 1; return Token::Percent },
+                '%' => { self.pos += 1; return self.op_assign(Token::Percent) },
                 '@' => { self.pos += 1; return Token::At },
                 ',' => { self.pos += 1; return Token::Comma },
-                '.' => { self.pos += 1; return Token::Dot },
+                ':' => { self.pos += 1; return Token::Colon },
+                '.' => {
+                    self.pos += 1;
+                    if self.input.get(self.pos) == Some(&'.') { self.pos += 1; return Token::DotDot; }
+                    return Token::Dot;
+                },
                 '[' => { self.pos += 1; return Token::LeftBracket },
                 ']' => { self.pos += 1; return Token::RightBracket },
-                '>' => { self.pos += 1; return Token::Greater },
-                '<' => { self.pos += 1; return Token::Less },
-                '+' => { self.pos += 1; return Token::Plus },
-                '-' => { self.pos += 1; return Token::Minus },
-                '*' => { self.pos += 1; return Token::Star },
-                '/' => { self.pos += 1; return Token::Slash },
+                '(' => { self.pos += 1; return Token::LeftParen },
+                ')' => { self.pos += 1; return Token::RightParen },
+                '>' | '<' => {
+                    self.pos += 1;
+                    let shift = self.input.get(self.pos) == Some(&ch);
+                    if shift { self.pos += 1; }
+                    return match (ch, shift) {
+                        ('>', true) => self.op_assign(Token::Shr),
+                        ('<', true) => self.op_assign(Token::Shl),
+                        ('>', false) => Token::Greater,
+                        _ => Token::Less,
+                    };
+                },
+                '&' => { self.pos += 1; return self.op_assign(Token::Amp) },
+                '|' => { self.pos += 1; return self.op_assign(Token::Pipe) },
+                '^' => { self.pos += 1; return self.op_assign(Token::Caret) },
+                '+' => { self.pos += 1; return self.op_assign(Token::Plus) },
+                '-' => { self.pos += 1; return self.op_assign(Token::Minus) },
+                '*' => { self.pos += 1; return self.op_assign(Token::Star) },
+                '/' => { self.pos += 1; return self.op_assign(Token::Slash) },
                 '=' => {
                     self.pos += 1;
                     if self.pos < self.input.len() && self.input[self.pos] == '=' {
                         self.pos += 1; return Token::Equal;
+                    } else if self.input.get(self.pos) == Some(&'>') {
+                        self.pos += 1; return Token::Arrow;
                     } else { return Token::Assign; }
                 },
                 '"' => return self.lex_string(),
@@ -54,6 +115,16 @@ impl Lexer {
         }
     }
 
+    /// Turns an operator followed by `=` into its compound assignment.
+    fn op_assign(&mut self, op: Token) -> Token {
+        if self.input.get(self.pos) == Some(&'=') {
+            self.pos += 1;
+            Token::OpAssign(Box::new(op))
+        } else {
+            op
+        }
+    }
+
     fn lex_identifier(&mut self) -> Token {
         let mut ident = String::new();
         while self.pos < self.input.len() && (self.input[self.pos].is_alphanumeric() || self.input[self.pos] == '_') {
@@ -64,8 +135,13 @@ impl Lexer {
             "Get" => Token::Get,
             "class" => Token::Class, 
             "new" => Token::New,
+            "stack" => Token::Stack,
+            "arena" => Token::Arena,
             "local" => Token::Local, 
+            "free" => Token::Free,
             "print" => Token::Print, 
+            "write" => Token::Write,
+            "eprint" => Token::EPrint,
             "rest" => Token::Rest,
             "if" => Token::If, 
             "then" => Token::Then, 
@@ -73,13 +149,28 @@ impl Lexer {
             "do" => Token::Do, 
             "is" => Token::Is, 
             "done" => Token::Done,
+            "for" => Token::For,
+            "match" => Token::Match,
+            "const" => Token::Const,
+            "enum" => Token::Enum,
+            "global" => Token::Global,
+            "export" => Token::Export,
+            "exit" => Token::Exit,
+            "in" => Token::In,
+            "break" => Token::Break,
+            "continue" => Token::Continue,
+            "true" => Token::True,
+            "false" => Token::False,
             _ => Token::Identifier(ident),
         }
     }
 
     fn lex_number(&mut self) -> Token {
         let mut n = String::new();
-        while self.pos < self.input.len() && (self.input[self.pos].is_digit(10) || self.input[self.pos] == '.') {
+        // A `.` followed by another one starts a range like `0..n`, not a fraction.
+        while self.pos < self.input.len()
+            && (self.input[self.pos].is_ascii_digit() || (self.input[self.pos] == '.' && self.input.get(self.pos + 1) != Some(&'.')))
+        {
             n.push(self.input[self.pos]); 
             self.pos += 1;
         }
@@ -1,26 +1,76 @@
+use std::collections::HashSet;
 use std::env;
 use std::fs;
 use std::process;
 
 mod lexer;
 mod parser;
+mod layout;
+mod consts;
+mod resolve;
+mod typeck;
+mod optimizer;
 mod generator;
+mod peephole;
+mod runtime;
 
 use lexer::Lexer;
 use parser::Parser;
-use generator::Generator;
+use generator::{Generator, Options};
 
 fn main() {
-    // Collect CLI arguments: hamer <filename>
-    let args: Vec<String> = env::args().collect();
-    
-    if args.len() < 2 {
+    // Collect CLI arguments: hamer [flags] <filename>
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (flags, files): (Vec<&String>, Vec<&String>) = args.iter().partition(|a| a.starts_with("--"));
+
+    if files.is_empty() {
         println!("H@mer Compiler v0.1");
-        println!("Usage: hamer <file.hmr>");
+        println!("Usage: hamer [flags] <file.hmr>");
+        println!("  --no-peephole[=rule,...]   disable all or some peephole rules");
+        println!("  --peephole-stats           report what each peephole rule removed");
+        println!("  --inline-runtime           expand print/alloc code at every use instead of calling shared routines");
+        println!("  --heap-size=<bytes>[K|M]   initial heap mapping and growth chunk (default 4K)");
+        println!("  --gc                       reclaim unreachable objects with a mark-sweep collector");
+        println!("  --bounds-check             trap on array indices out of range");
         process::exit(1);
     }
 
-    let file_path = &args[1];
+    let file_path = files[0];
+
+    // Peephole rules can be switched off one by one to measure what each buys.
+    let all_rules: HashSet<String> = peephole::RULES.iter().map(|r| r.name.to_string()).collect();
+    let mut disabled_rules = HashSet::new();
+    let mut peephole_stats = false;
+    let mut opts = Options::default();
+    for flag in &flags {
+        match flag.split_once('=') {
+            None if flag.as_str() == "--no-peephole" => disabled_rules.extend(all_rules.iter().cloned()),
+            None if flag.as_str() == "--peephole-stats" => peephole_stats = true,
+            None if flag.as_str() == "--inline-runtime" => opts.inline_runtime = true,
+            None if flag.as_str() == "--gc" => opts.gc = true,
+            None if flag.as_str() == "--bounds-check" => opts.bounds_check = true,
+            Some(("--heap-size", size)) => match parse_size(size) {
+                Some(n) if n > 0 => opts.heap_size = n,
+                _ => {
+                    eprintln!("[ERROR] invalid heap size `{}`", size);
+                    process::exit(1);
+                }
+            },
+            Some(("--no-peephole", rules)) => {
+                for rule in rules.split(',') {
+                    if !all_rules.contains(rule) {
+                        eprintln!("[ERROR] unknown peephole rule `{}`", rule);
+                        process::exit(1);
+                    }
+                    disabled_rules.insert(rule.to_string());
+                }
+            }
+            _ => {
+                eprintln!("[ERROR] unknown flag `{}`", flag);
+                process::exit(1);
+            }
+        }
+    }
     
     // 1. Read the H@mer source file
     let input = fs::read_to_string(file_path).expect("Could not read source file");
@@ -28,22 +78,71 @@ fn main() {
     println!("[H@mer] Tokenizing...");
     // 2. Lexical Analysis (Tokens)
     let mut lexer = Lexer::new(input);
-    let mut tokens = Vec::new();
-    loop {
-        let token = lexer.next_token();
-        if token == lexer::Token::EOF { break; }
-        tokens.push(token);
-    }
+    let (tokens, spans) = lexer.tokenize_spanned();
 
     println!("[H@mer] Parsing AST...");
     // 3. Syntax Analysis (Abstract Syntax Tree)
-    let mut parser = Parser::new(tokens);
-    let ast = parser.parse_program();
+    let mut parser = Parser::new(tokens, spans);
+    let mut ast = parser.parse_program();
+
+    println!("[H@mer] Folding constants...");
+    let (errors, warnings) = consts::fold(&mut ast);
+    for w in &warnings {
+        eprintln!("[WARNING] {}:{}", file_path, w);
+    }
+    for e in &errors {
+        eprintln!("[ERROR] {}:{}", file_path, e);
+    }
+    if !errors.is_empty() {
+        process::exit(1);
+    }
+
+    println!("[H@mer] Resolving names...");
+    let (errors, warnings) = resolve::resolve(&ast);
+    for w in &warnings {
+        eprintln!("[WARNING] {}:{}", file_path, w);
+    }
+    for e in &errors {
+        eprintln!("[ERROR] {}:{}", file_path, e);
+    }
+    if !errors.is_empty() {
+        process::exit(1);
+    }
+
+    println!("[H@mer] Checking types...");
+    let errors = typeck::check(&ast, opts.gc);
+    for e in &errors {
+        eprintln!("[ERROR] {}:{}", file_path, e);
+    }
+    if !errors.is_empty() {
+        process::exit(1);
+    }
+
+    println!("[H@mer] Eliminating dead code...");
+    let (ast, warnings) = optimizer::eliminate_dead_code(ast);
+    for w in &warnings {
+        eprintln!("[WARNING] {}:{}", file_path, w);
+    }
 
     println!("[H@mer] Generating ARM64 Assembly...");
     // 4. Code Generation
-    let mut generator = Generator::new();
+    let mut generator = Generator::new(opts);
     let assembly = generator.generate(ast);
+    for e in &generator.errors {
+        eprintln!("[ERROR] {}:{}", file_path, e);
+    }
+    if !generator.errors.is_empty() {
+        process::exit(1);
+    }
+
+    println!("[H@mer] Running peephole optimizer...");
+    let (assembly, stats) = peephole::optimize(&assembly, &disabled_rules);
+    if peephole_stats {
+        for (rule, count) in &stats {
+            let state = if disabled_rules.contains(*rule) { " (disabled)" } else { "" };
+            println!("[H@mer]   {:<12} {} rewrites{}", rule, count, state);
+        }
+    }
 
     // 5. Output to out.s (Assembly file)
     fs::write("out.s", assembly).expect("Could not write assembly file");
@@ -52,4 +151,14 @@ fn main() {
     println!("Next steps:");
     println!("  as out.s -o out.o");
     println!("  ld out.o -o hamer_prog");
-}
\ No newline at end of file
+}
+
+/// Parses `4096`, `64K` or `1M` into bytes.
+fn parse_size(s: &str) -> Option<usize> {
+    let (digits, scale) = match s.chars().last()? {
+        'K' | 'k' => (&s[..s.len() - 1], 1024),
+        'M' | 'm' => (&s[..s.len() - 1], 1024 * 1024),
+        _ => (s, 1),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(scale)
+}
@@ -0,0 +1,281 @@
+use std::collections::HashSet;
+use crate::lexer::Span;
+use crate::parser::{MatchArm, Stmt};
+
+/// What the program reads: variable names and instantiated classes.
+#[derive(Default)]
+struct Usage {
+    reads: HashSet<String>,
+    classes: HashSet<String>,
+    /// The names declared `global`, to tell them apart in warnings.
+    globals: HashSet<String>,
+}
+
+struct DeadCode {
+    warnings: Vec<String>,
+    /// Messages already given, so each dead name is reported once, at its first write.
+    warned: HashSet<String>,
+    /// The last statement position seen, for statements that don't record their own.
+    last_span: Span,
+}
+
+/// Removes statements that never run and definitions nothing uses.
+/// Returns the pruned AST and warnings for user-written dead code, each starting with
+/// the `line:col` it points at; code merged in with `Get` is pruned silently.
+pub fn eliminate_dead_code(mut ast: Vec<Stmt>) -> (Vec<Stmt>, Vec<String>) {
+    let mut dce = DeadCode { warnings: Vec::new(), warned: HashSet::new(), last_span: Span::default() };
+
+    // Libraries first: a `Get` block goes away when user code touches none of its names.
+    // Raw assembly after it may count on the registers its locals occupy, so that pins it.
+    let mut user = Usage::default();
+    collect_usage(&ast, &mut user, false);
+    let mut kept = Vec::new();
+    let mut asm_after = false;
+    for s in ast.into_iter().rev() {
+        match &s {
+            Stmt::MergeBlock(sub) => {
+                let mut defs = Usage::default();
+                collect_defs(sub, &mut defs);
+                let used = defs.reads.iter().any(|n| user.reads.contains(n))
+                    || defs.classes.iter().any(|c| user.classes.contains(c));
+                if !used && !asm_after { continue; }
+            }
+            other => asm_after |= has_asm(other),
+        }
+        kept.push(s);
+    }
+    kept.reverse();
+    ast = kept;
+
+    // Then prune to a fixed point: dropping a dead local can orphan its class,
+    // dropping unreachable code can orphan the locals it read.
+    loop {
+        let before = count(&ast);
+        let mut usage = Usage::default();
+        collect_usage(&ast, &mut usage, true);
+        let mut pinned = HashSet::new();
+        let mut declared = Vec::new();
+        collect_pinned(&ast, &mut declared, &mut pinned);
+        ast = dce.prune(ast, &usage, &pinned, false);
+        if count(&ast) == before { break; }
+    }
+    (ast, dce.warnings)
+}
+
+impl DeadCode {
+    fn warn(&mut self, in_lib: bool, at: Option<Span>, msg: String) {
+        let at = at.unwrap_or(self.last_span);
+        if !in_lib && self.warned.insert(msg.clone()) { self.warnings.push(format!("{}: {}", at, msg)); }
+    }
+
+    fn prune(&mut self, stmts: Vec<Stmt>, usage: &Usage, pinned: &HashSet<String>, in_lib: bool) -> Vec<Stmt> {
+        let mut out = Vec::new();
+        let mut iter = stmts.into_iter();
+        while let Some(stmt) = iter.next() {
+            if let Some(span) = stmt.span() { self.last_span = span; }
+            let written = match &stmt {
+                Stmt::LocalAssign { name, .. } | Stmt::GlobalDef { name, .. }
+                | Stmt::HeapAlloc { var_name: name, .. } | Stmt::StackAlloc { var_name: name, .. } => Some(name),
+                // Field writes to an object nobody reads are as dead as the object.
+                Stmt::FieldAssign { path, .. } | Stmt::FieldMath { path, .. } => path.first(),
+                _ => None,
+            };
+            if let Some(name) = written.filter(|n| !usage.reads.contains(*n)) {
+                // Pinned locals still get the warning, they just keep their register.
+                let kind = if usage.globals.contains(name) { "global" } else { "local" };
+                self.warn(in_lib, stmt.span(), format!("{} `{}` is written but never read", kind, name));
+                if !pinned.contains(name) { continue; }
+            }
+            let stmt = match stmt {
+                Stmt::ClassDef { name, span, .. } if !usage.classes.contains(&name) => {
+                    self.warn(in_lib, Some(span), format!("class `{}` is never instantiated", name));
+                    continue;
+                }
+                Stmt::IfStmt { cond, body, span } => {
+                    Stmt::IfStmt { cond, body: self.prune(body, usage, pinned, in_lib), span }
+                }
+                Stmt::WhileStmt { cond, body, label, span } => {
+                    Stmt::WhileStmt { cond, body: self.prune(body, usage, pinned, in_lib), label, span }
+                }
+                Stmt::ForStmt { var, start, end, step, body, label, span } => {
+                    Stmt::ForStmt { var, start, end, step, body: self.prune(body, usage, pinned, in_lib), label, span }
+                }
+                Stmt::ProbIf { chance, chance_const, body, span } => {
+                    Stmt::ProbIf { chance, chance_const, body: self.prune(body, usage, pinned, in_lib), span }
+                }
+                Stmt::Match { value, arms, span } => {
+                    let arms = arms.into_iter()
+                        .map(|a| MatchArm { body: self.prune(a.body, usage, pinned, in_lib), ..a })
+                        .collect();
+                    Stmt::Match { value, arms, span }
+                }
+                Stmt::Arena(body) => Stmt::Arena(self.prune(body, usage, pinned, in_lib)),
+                Stmt::MergeBlock(sub) => {
+                    let sub = self.prune(sub, usage, pinned, true);
+                    if sub.is_empty() { continue; }
+                    Stmt::MergeBlock(sub)
+                }
+                other => other,
+            };
+            let exit = terminates(&stmt);
+            let at = stmt.span();
+            out.push(stmt);
+            if let Some(exit) = exit {
+                // Point at the dead code itself when it knows where it is.
+                if let Some(next) = iter.next() { self.warn(in_lib, next.span().or(at), format!("unreachable code after {}", exit)); }
+                break;
+            }
+        }
+        out
+    }
+}
+
+fn collect_usage(stmts: &[Stmt], usage: &mut Usage, into_libs: bool) {
+    for s in stmts {
+        match s {
+            Stmt::HeapAlloc { class_name, .. } | Stmt::StackAlloc { class_name, .. } => { usage.classes.insert(class_name.clone()); }
+            // The target of a write isn't a read, but its index and the new value are.
+            Stmt::LocalAssign { value: e, .. } => e.visit_paths(&mut |p| { usage.reads.insert(p[0].clone()); }),
+            // Code linked against an exported global may read it.
+            Stmt::GlobalDef { name, export, .. } => {
+                usage.globals.insert(name.clone());
+                if *export { usage.reads.insert(name.clone()); }
+            }
+            Stmt::FieldAssign { path, value: e } | Stmt::FieldMath { path, rhs: e, .. } => {
+                if let Some(i) = &path.index { i.visit_paths(&mut |p| { usage.reads.insert(p[0].clone()); }); }
+                e.visit_paths(&mut |p| { usage.reads.insert(p[0].clone()); });
+            }
+            Stmt::Free { value: e, .. } | Stmt::Exit { status: e, .. } => e.visit_paths(&mut |p| { usage.reads.insert(p[0].clone()); }),
+            Stmt::Print { items, .. } => {
+                for e in items {
+                    e.visit_paths(&mut |p| { usage.reads.insert(p[0].clone()); });
+                }
+            }
+            Stmt::IfStmt { cond, body, .. } | Stmt::WhileStmt { cond, body, .. } => {
+                cond.visit_paths(&mut |p| { usage.reads.insert(p[0].clone()); });
+                collect_usage(body, usage, into_libs);
+            }
+            Stmt::ForStmt { start, end, step, body, .. } => {
+                for e in [Some(start), Some(end), step.as_ref()].into_iter().flatten() {
+                    e.visit_paths(&mut |p| { usage.reads.insert(p[0].clone()); });
+                }
+                collect_usage(body, usage, into_libs);
+            }
+            Stmt::Match { value, arms, .. } => {
+                value.visit_paths(&mut |p| { usage.reads.insert(p[0].clone()); });
+                for arm in arms {
+                    for v in &arm.values { v.visit_paths(&mut |p| { usage.reads.insert(p[0].clone()); }); }
+                    collect_usage(&arm.body, usage, into_libs);
+                }
+            }
+            Stmt::Arena(body) => collect_usage(body, usage, into_libs),
+            Stmt::ProbIf { body, .. } => {
+                // Chaos rolls keep their seed in the `math` object.
+                usage.reads.insert("math".into());
+                collect_usage(body, usage, into_libs);
+            }
+            Stmt::MergeBlock(sub) if into_libs => collect_usage(sub, usage, into_libs),
+            _ => {}
+        }
+    }
+}
+
+fn collect_defs(stmts: &[Stmt], defs: &mut Usage) {
+    for s in stmts {
+        match s {
+            Stmt::ClassDef { name, .. } => { defs.classes.insert(name.clone()); }
+            Stmt::LocalAssign { name, .. } | Stmt::GlobalDef { name, .. }
+            | Stmt::HeapAlloc { var_name: name, .. } | Stmt::StackAlloc { var_name: name, .. } => { defs.reads.insert(name.clone()); }
+            Stmt::MergeBlock(sub) => collect_defs(sub, defs),
+            _ => {}
+        }
+    }
+}
+
+/// Locals are numbered into registers in declaration order, so a local declared
+/// before raw assembly can't be removed without renumbering what that assembly sees.
+/// A global declared before it stays too, since the assembly may use its symbol.
+fn collect_pinned(stmts: &[Stmt], declared: &mut Vec<String>, pinned: &mut HashSet<String>) {
+    for s in stmts {
+        match s {
+            Stmt::LocalAssign { name, .. } | Stmt::GlobalDef { name, .. }
+            | Stmt::HeapAlloc { var_name: name, .. } | Stmt::StackAlloc { var_name: name, .. } => declared.push(name.clone()),
+            Stmt::AsmBlock(code) | Stmt::IntelBlock(code) if !code.is_empty() => pinned.extend(declared.iter().cloned()),
+            Stmt::ForStmt { var, body, .. } => {
+                declared.push(var.clone());
+                collect_pinned(body, declared, pinned);
+            }
+            Stmt::Match { arms, .. } => for a in arms { collect_pinned(&a.body, declared, pinned) },
+            Stmt::IfStmt { body, .. } | Stmt::WhileStmt { body, .. } | Stmt::ProbIf { body, .. } | Stmt::Arena(body) | Stmt::MergeBlock(body) => {
+                collect_pinned(body, declared, pinned)
+            }
+            _ => {}
+        }
+    }
+}
+
+fn has_asm(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::AsmBlock(code) | Stmt::IntelBlock(code) => !code.is_empty(),
+        Stmt::IfStmt { body, .. } | Stmt::WhileStmt { body, .. } | Stmt::ForStmt { body, .. } | Stmt::ProbIf { body, .. }
+        | Stmt::Arena(body) | Stmt::MergeBlock(body) => {
+            body.iter().any(has_asm)
+        }
+        Stmt::Match { arms, .. } => arms.iter().any(|a| a.body.iter().any(has_asm)),
+        _ => false,
+    }
+}
+
+/// What ends control flow at this statement, if it never falls through.
+fn terminates(stmt: &Stmt) -> Option<&'static str> {
+    match stmt {
+        Stmt::AsmBlock(code) if asm_exits(code) => Some("exit"),
+        Stmt::Exit { .. } => Some("`exit`"),
+        Stmt::Break { .. } => Some("`break`"),
+        Stmt::Continue { .. } => Some("`continue`"),
+        _ => None,
+    }
+}
+
+/// Splits raw assembly into instructions, each a mnemonic and its operands. The parser
+/// may break an instruction across lines, so this goes by the commas between operands.
+fn asm_instructions(code: &str) -> Vec<(&str, Vec<&str>)> {
+    let mut out = Vec::new();
+    let mut words = code.split_whitespace();
+    while let Some(op) = words.next() {
+        let mut operands = Vec::new();
+        let mut depth = 0;
+        if !matches!(op, "ret" | "nop") {
+            for w in words.by_ref() {
+                operands.push(w.trim_end_matches(','));
+                depth += w.matches('[').count() as i32 - w.matches(']').count() as i32;
+                if depth <= 0 && !w.ends_with(',') { break; }
+            }
+        }
+        out.push((op, operands));
+    }
+    out
+}
+
+/// Whether raw assembly always exits: it ends in `svc` with x8 last set to exit (93)
+/// or exit_group (94), and has no branch that could go around that.
+fn asm_exits(code: &str) -> bool {
+    let insns = asm_instructions(code);
+    let Some(((last, _), rest)) = insns.split_last() else { return false };
+    let branches = |m: &str| m == "b" || m.starts_with("b.") || matches!(m, "br" | "cbz" | "cbnz" | "tbz" | "tbnz" | "ret");
+    if *last != "svc" || rest.iter().any(|(m, _)| branches(m)) { return false; }
+    // The last instruction that writes x8 decides the syscall.
+    match rest.iter().rev().find(|(_, ops)| matches!(ops.first(), Some(&"x8" | &"w8"))) {
+        Some((op, ops)) => *op == "mov" && ops.len() == 2 && matches!(ops[1].trim_start_matches('#'), "93" | "94"),
+        None => false,
+    }
+}
+
+fn count(stmts: &[Stmt]) -> usize {
+    stmts.iter().map(|s| match s {
+        Stmt::IfStmt { body, .. } | Stmt::WhileStmt { body, .. } | Stmt::ForStmt { body, .. } | Stmt::ProbIf { body, .. }
+        | Stmt::Arena(body) | Stmt::MergeBlock(body) => 1 + count(body),
+        Stmt::Match { arms, .. } => 1 + arms.iter().map(|a| count(&a.body)).sum::<usize>(),
+        _ => 1,
+    }).sum()
+}
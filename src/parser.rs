@@ -1,28 +1,314 @@
-use crate::lexer::Token;
+use crate::lexer::{Lexer, Span, Token};
 use std::fs;
+use std::ops::Deref;
 
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Number(f64),
+    Str(String),
+    Path(Path),
+    Neg(Box<Expr>),
+    /// `{value:spec}` inside an interpolated string.
+    Format { value: Box<Expr>, spec: FormatSpec },
+    /// A string literal with `{...}` holes, split into `Str` and `Format` parts.
+    Interp(Vec<Expr>),
+    Bool(bool),
+    /// Arithmetic, or a comparison (`==`, `<`, `>`) giving a bool.
+    Binary { op: Token, lhs: Box<Expr>, rhs: Box<Expr> },
+    /// A built-in function: `len(s)`, `str(n)` or `int(s)`.
+    Call { name: String, args: Vec<Expr>, span: Span },
+    /// `s[start:end]`; either end may be left out.
+    Slice { value: Box<Expr>, start: Option<Box<Expr>>, end: Option<Box<Expr>> },
+}
+
+impl Expr {
+    /// The value of an integer constant, as `match` arms need.
+    pub fn const_int(&self) -> Option<i64> {
+        match self {
+            Expr::Number(n) if n.fract() == 0.0 => Some(*n as i64),
+            _ => None,
+        }
+    }
+
+    /// Calls `f` on every variable or field path the expression reads.
+    pub fn visit_paths(&self, f: &mut impl FnMut(&[String])) {
+        match self {
+            Expr::Path(p) => {
+                f(p);
+                if let Some(i) = &p.index { i.visit_paths(f) }
+            }
+            Expr::Neg(e) | Expr::Format { value: e, .. } => e.visit_paths(f),
+            Expr::Interp(parts) => for p in parts { p.visit_paths(f) },
+            Expr::Binary { lhs, rhs, .. } => { lhs.visit_paths(f); rhs.visit_paths(f); }
+            Expr::Call { args, .. } => for a in args { a.visit_paths(f) },
+            Expr::Slice { value, start, end } => {
+                value.visit_paths(f);
+                for e in [start, end].into_iter().flatten() { e.visit_paths(f) }
+            }
+            Expr::Number(_) | Expr::Str(_) | Expr::Bool(_) => {}
+        }
+    }
+}
+
+/// A variable or field path such as `player.hp` or `xs[i]`, with where it was written.
+/// Derefs to its names, so it can be used wherever a `&[String]` is expected.
+#[derive(Debug, Clone)]
+pub struct Path {
+    pub names: Vec<String>,
+    /// The element index when the path ends in `[...]`.
+    pub index: Option<Box<Expr>>,
+    pub span: Span,
+}
+
+impl Path {
+    /// Whether the path names a local itself rather than memory it points to.
+    pub fn is_local(&self) -> bool {
+        self.names.len() == 1 && self.index.is_none()
+    }
+}
+
+impl Deref for Path {
+    type Target = [String];
+    fn deref(&self) -> &[String] { &self.names }
+}
+
+/// How an interpolated value is printed: `{n:+08x}` is sign, zero-pad, width 8, hex;
+/// `{f:.2}` prints a float with two fraction digits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatSpec {
+    pub base: u8,
+    /// `Some(false)` for `u`, `Some(true)` for `d`, `None` to follow the value's type.
+    pub signed: Option<bool>,
+    pub plus: bool,
+    pub zero_pad: bool,
+    pub upper: bool,
+    pub width: u8,
+    /// Fraction digits for floats, at most 18.
+    pub precision: Option<u8>,
+}
+
+impl Default for FormatSpec {
+    fn default() -> Self {
+        Self { base: 10, signed: None, plus: false, zero_pad: false, upper: false, width: 0, precision: None }
+    }
+}
+
+impl FormatSpec {
+    /// Parses `[+][0][width][.precision][d|u|x|X|b]`. Widths are capped at 64 to fit the runtime buffer.
+    fn parse(spec: &str) -> Self {
+        let mut f = FormatSpec::default();
+        let mut chars = spec.chars().peekable();
+        if chars.peek() == Some(&'+') { f.plus = true; chars.next(); }
+        if chars.peek() == Some(&'0') { f.zero_pad = true; chars.next(); }
+        let mut width = 0u32;
+        while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+            width = (width * 10 + d).min(64);
+            chars.next();
+        }
+        f.width = width as u8;
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            let mut precision = 0u32;
+            while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+                precision = (precision * 10 + d).min(18);
+                chars.next();
+            }
+            f.precision = Some(precision as u8);
+        }
+        match chars.next() {
+            Some('d') => f.signed = Some(true),
+            Some('u') => f.signed = Some(false),
+            Some('x') => f.base = 16,
+            Some('X') => { f.base = 16; f.upper = true; }
+            Some('b') => f.base = 2,
+            _ => {}
+        }
+        // Hex and binary show the raw bits unless `d` asked otherwise.
+        if f.base != 10 && f.signed.is_none() { f.signed = Some(false); }
+        f
+    }
+
+    /// Whether the spec asks for an integer (`d`, `u`, `x`, `X`, `b`), which truncates a float.
+    pub fn is_integer(&self) -> bool {
+        self.signed.is_some()
+    }
+}
+
+/// Splits `"hp={p.hp} addr={ptr:x}"` into literal and formatted parts.
+/// `{{` and `}}` stand for literal braces.
+fn parse_interpolated(s: &str, span: Span) -> Expr {
+    let mut parts = Vec::new();
+    let mut lit = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => { chars.next(); lit.push('{'); }
+            '}' if chars.peek() == Some(&'}') => { chars.next(); lit.push('}'); }
+            '{' => {
+                let mut inner = String::new();
+                for c in chars.by_ref() {
+                    if c == '}' { break; }
+                    inner.push(c);
+                }
+                // The spec starts at the first `:` that isn't part of a slice like `{s[1:3]}`.
+                let mut depth = 0;
+                let colon = inner.char_indices().find(|&(_, c)| {
+                    match c {
+                        '[' | '(' => depth += 1,
+                        ']' | ')' => depth -= 1,
+                        _ => {}
+                    }
+                    c == ':' && depth == 0
+                });
+                let (src, spec) = match colon {
+                    Some((i, _)) => (&inner[..i], &inner[i + 1..]),
+                    None => (inner.as_str(), ""),
+                };
+                // Holes report the position of the string they are in.
+                let tokens = Lexer::new(src.to_string()).tokenize();
+                let spans = vec![span; tokens.len()];
+                let value = Parser::new(tokens, spans).parse_expr();
+                if !lit.is_empty() { parts.push(Expr::Str(std::mem::take(&mut lit))); }
+                parts.push(Expr::Format { value: Box::new(value), spec: FormatSpec::parse(spec) });
+            }
+            _ => lit.push(c),
+        }
+    }
+    if !lit.is_empty() { parts.push(Expr::Str(lit)); }
+    Expr::Interp(parts)
+}
+
+/// Whether a binary operator compares its operands, giving a bool.
+pub fn is_compare(op: &Token) -> bool {
+    matches!(op, Token::Equal | Token::Less | Token::Greater)
+}
+
+/// Binding power of a binary operator; higher binds tighter. The order follows C.
+fn binary_prec(op: &Token) -> Option<u8> {
+    match op {
+        Token::Equal | Token::Less | Token::Greater => Some(0),
+        Token::Pipe => Some(1),
+        Token::Caret => Some(2),
+        Token::Amp => Some(3),
+        Token::Shl | Token::Shr => Some(4),
+        Token::Plus | Token::Minus => Some(5),
+        Token::Star | Token::Slash | Token::Percent => Some(6),
+        _ => None,
+    }
+}
+
+/// A class field: `name`, `name: u16`, `name: u32 @12` or `name: [16]u8`.
+#[derive(Debug)]
+pub struct Field {
+    pub name: String,
+    pub span: Span,
+    pub ty: Option<String>,
+    pub offset: Option<usize>,
+    /// The constant an `@offset` was written as, until it is folded into `offset`.
+    pub offset_const: Option<String>,
+    /// Element count of an array field.
+    pub len: Option<usize>,
+    /// The constant an array field's length was written as, until it is folded into `len`.
+    pub len_const: Option<String>,
+}
+
+/// A member of an `enum`: `Idle` or `Dead = 10`. Members without a value count up from the one before.
+#[derive(Debug)]
+pub struct Variant {
+    pub name: String,
+    pub value: Option<Expr>,
+    pub span: Span,
+}
+
+/// One arm of a `match`: `1 | 2 => body`. A `_` arm has no values and matches anything.
+#[derive(Debug)]
+pub struct MatchArm {
+    pub values: Vec<Expr>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum Stmt {
-    LocalAssign { name: String, value: f64 },
-    ClassDef { name: String, fields: Vec<String> },
-    HeapAlloc { var_name: String, class_name: String },
-    FieldAssign { path: Vec<String>, value: f64 },
-    FieldMath { path: Vec<String>, op: Token, rhs_val: f64 },
-    PrintVar(String),
-    PrintString(String),
-    IfStmt { path: Vec<String>, op: Token, rhs_val: f64, body: Vec<Stmt> },
-    ProbIf { chance: f64, body: Vec<Stmt> },
-    WhileStmt { path: Vec<String>, op: Token, rhs_val: f64, body: Vec<Stmt> },
-    AsmBlock(String),      
+    LocalAssign { name: String, ty: Option<String>, value: Expr, span: Span },
+    /// `class Name [packed] is field... done`; `packed` drops the alignment padding.
+    ClassDef { name: String, fields: Vec<Field>, packed: bool, span: Span },
+    /// `const NAME = value`, folded into every use by `consts::fold`.
+    Const { name: String, value: Expr, span: Span },
+    /// `enum Name is A B = 5 C done`; `Name.A` stands for the member's value.
+    EnumDef { name: String, variants: Vec<Variant>, span: Span },
+    /// `[export] global name[: type] [= value]`: a variable in `.data`, or `.bss` when it
+    /// starts at zero. `export` makes the symbol visible to the linker.
+    GlobalDef { name: String, ty: Option<String>, value: Option<Expr>, export: bool, span: Span },
+    /// `local p = new Point`; `class_name` is an array type like `[64]u64` for `new [64]u64`.
+    HeapAlloc { var_name: String, class_name: String, span: Span },
+    /// `local p = stack Point`: lives in the stack until the enclosing block ends.
+    StackAlloc { var_name: String, class_name: String, span: Span },
+    /// `arena is ... done`: everything the block allocates is released at `done`.
+    Arena(Vec<Stmt>),
+    /// `free value` hands an object back to the heap.
+    Free { value: Expr, span: Span },
+    /// `path = value`.
+    FieldAssign { path: Path, value: Expr },
+    /// `path op= rhs`, also written `path = path op rhs`: one load, modify and store.
+    FieldMath { path: Path, op: Token, rhs: Expr },
+    /// `print`, `write` (no trailing newline) and `eprint` (stderr).
+    Print { items: Vec<Expr>, fd: u8, newline: bool, span: Span },
+    /// `if cond is ... done`; `cond` is a bool expression.
+    IfStmt { cond: Expr, body: Vec<Stmt>, span: Span },
+    /// `if ?<%30> is ... done` runs its body that percent of the time. A chance
+    /// written as a constant is kept in `chance_const` until it is folded.
+    ProbIf { chance: f64, chance_const: Option<String>, body: Vec<Stmt>, span: Span },
+    /// `[label:] while cond is ... done`.
+    WhileStmt { cond: Expr, body: Vec<Stmt>, label: Option<String>, span: Span },
+    /// `[label:] for var in start..end [step n] is ... done`; `end` is exclusive.
+    ForStmt { var: String, start: Expr, end: Expr, step: Option<Expr>, body: Vec<Stmt>, label: Option<String>, span: Span },
+    /// `match value is 0 => ... 1 | 2 => ... _ => ... done`.
+    Match { value: Expr, arms: Vec<MatchArm>, span: Span },
+    /// `break` or `continue`, leaving the innermost loop or the one named by `label`.
+    Break { label: Option<String>, span: Span },
+    Continue { label: Option<String>, span: Span },
+    /// `exit status` ends the program with `status`; a bare `exit` with 0.
+    Exit { status: Expr, span: Span },
+    /// Raw assembly from `@asm`. Empty when it stands in for a token the parser skipped.
+    AsmBlock(String),
     IntelBlock(String),    
     PythonBlock(String),   
-    MergeBlock(String),    
+    MergeBlock(Vec<Stmt>), 
+}
+
+impl Stmt {
+    /// Where the statement was written, for the kinds that record it.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Stmt::LocalAssign { span, .. } | Stmt::ClassDef { span, .. } | Stmt::Const { span, .. }
+            | Stmt::EnumDef { span, .. } | Stmt::GlobalDef { span, .. } | Stmt::HeapAlloc { span, .. }
+            | Stmt::StackAlloc { span, .. } | Stmt::Print { span, .. } | Stmt::ForStmt { span, .. }
+            | Stmt::Match { span, .. } | Stmt::Break { span, .. } | Stmt::Continue { span, .. }
+            | Stmt::Exit { span, .. } | Stmt::ProbIf { span, .. } | Stmt::IfStmt { span, .. }
+            | Stmt::WhileStmt { span, .. } | Stmt::Free { span, .. } => Some(*span),
+            Stmt::FieldAssign { path, .. } | Stmt::FieldMath { path, .. } => Some(path.span),
+            _ => None,
+        }
+    }
 }
 
-pub struct Parser { pub tokens: Vec<Token>, pub pos: usize }
+pub struct Parser {
+    pub tokens: Vec<Token>,
+    pub spans: Vec<Span>,
+    pub pos: usize,
+    /// Parsing a file pulled in with `get`, whose last line doesn't end the program.
+    pub lib: bool,
+}
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self { Self { tokens, pos: 0 } }
+    pub fn new(tokens: Vec<Token>, spans: Vec<Span>) -> Self { Self { tokens, spans, pos: 0, lib: false } }
+
+    /// Where the next token starts.
+    fn span(&self) -> Span {
+        self.spans.get(self.pos).or(self.spans.last()).copied().unwrap_or_default()
+    }
     
     fn advance(&mut self) -> Token {
         let t = self.peek();
@@ -48,7 +334,118 @@ impl Parser {
         stmts
     }
 
-    fn parse_path(&mut self) -> Vec<String> {
+    pub fn parse_expr(&mut self) -> Expr {
+        self.parse_binary(0)
+    }
+
+    fn parse_binary(&mut self, min_prec: u8) -> Expr {
+        let mut lhs = self.parse_primary();
+        while let Some(prec) = binary_prec(&self.peek()) {
+            // An operator opening a line doesn't continue the expression above it,
+            // so after `print x` a `-1 => ...` line is the next `match` arm.
+            if prec < min_prec || self.line_start() { break; }
+            let op = self.advance();
+            let rhs = self.parse_binary(prec + 1);
+            lhs = Expr::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs) };
+        }
+        lhs
+    }
+
+    fn parse_primary(&mut self) -> Expr {
+        let e = match self.peek() {
+            Token::Number(n) => { self.advance(); Expr::Number(n) }
+            Token::True | Token::False => Expr::Bool(self.advance() == Token::True),
+            Token::StringLit(s) => {
+                let span = self.span();
+                self.advance();
+                if s.contains('{') || s.contains('}') { parse_interpolated(&s, span) } else { Expr::Str(s) }
+            }
+            Token::Identifier(name) if self.tokens.get(self.pos + 1) == Some(&Token::LeftParen) => {
+                let span = self.span();
+                self.advance();
+                self.advance();
+                let mut args = Vec::new();
+                while !matches!(self.peek(), Token::RightParen | Token::EOF) {
+                    args.push(self.parse_expr());
+                    if self.peek() != Token::Comma { break; }
+                    self.advance();
+                }
+                if self.peek() == Token::RightParen { self.advance(); }
+                Expr::Call { name, args, span }
+            }
+            Token::Identifier(_) => Expr::Path(self.parse_path()),
+            Token::Minus => {
+                self.advance();
+                match self.parse_primary() {
+                    Expr::Number(n) => Expr::Number(-n),
+                    e => Expr::Neg(Box::new(e)),
+                }
+            }
+            Token::LeftParen => {
+                self.advance();
+                let e = self.parse_expr();
+                if self.peek() == Token::RightParen { self.advance(); }
+                e
+            }
+            // Leave the token for the statement parser rather than eating a `done`.
+            _ => Expr::Number(0.0),
+        };
+        self.parse_slice(e)
+    }
+
+    /// `value[start:end]` if a slice follows.
+    fn parse_slice(&mut self, value: Expr) -> Expr {
+        if self.peek() != Token::LeftBracket || !self.at_slice() { return value; }
+        self.advance();
+        let start = (self.peek() != Token::Colon).then(|| Box::new(self.parse_expr()));
+        if self.peek() == Token::Colon { self.advance(); }
+        let end = (self.peek() != Token::RightBracket).then(|| Box::new(self.parse_expr()));
+        if self.peek() == Token::RightBracket { self.advance(); }
+        Expr::Slice { value: Box::new(value), start, end }
+    }
+
+    /// Whether the next token is the first on its line.
+    fn line_start(&self) -> bool {
+        match (self.pos.checked_sub(1).and_then(|p| self.spans.get(p)), self.spans.get(self.pos)) {
+            (Some(prev), Some(next)) => next.line > prev.line,
+            _ => true,
+        }
+    }
+
+    /// Whether a `match` arm starts here: a line of values or `_`, separated by `|`, then `=>`.
+    fn at_arm(&self) -> bool {
+        if !self.line_start() { return false; }
+        let line = self.span().line;
+        for (t, s) in self.tokens[self.pos..].iter().zip(&self.spans[self.pos..]) {
+            if s.line != line { return false; }
+            match t {
+                Token::Arrow => return true,
+                Token::Number(_) | Token::Minus | Token::Identifier(_) | Token::Dot | Token::Pipe => {}
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// Whether the `[...]` starting at the current token has a `:` at its top level.
+    fn at_slice(&self) -> bool {
+        let mut depth = 0;
+        for t in &self.tokens[self.pos..] {
+            match t {
+                Token::LeftBracket | Token::LeftParen => depth += 1,
+                Token::RightBracket | Token::RightParen => {
+                    depth -= 1;
+                    if depth == 0 { return false; }
+                }
+                Token::Colon if depth == 1 => return true,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    fn parse_path(&mut self) -> Path {
+        let span = self.span();
         let mut path = Vec::new();
         if let Token::Identifier(s) = self.peek() {
             self.advance(); 
@@ -61,7 +458,25 @@ impl Parser {
                 } else { break; }
             }
         }
-        path
+        let mut index = None;
+        if !path.is_empty() && self.peek() == Token::LeftBracket && !self.at_slice() {
+            self.advance();
+            index = Some(Box::new(self.parse_expr()));
+            if self.peek() == Token::RightBracket { self.advance(); }
+        }
+        Path { names: path, index, span }
+    }
+
+    /// `[64]u64` after its `[`: the length and element type. The length may name a constant.
+    fn parse_array_type(&mut self) -> (String, String) {
+        let len = match self.advance() {
+            Token::Number(n) => (n as usize).to_string(),
+            Token::Identifier(c) => c,
+            _ => "0".into(),
+        };
+        if self.peek() == Token::RightBracket { self.advance(); }
+        let elem = if let Token::Identifier(t) = self.advance() { t } else { "".into() };
+        (len, elem)
     }
 
     fn parse_statement(&mut self) -> Stmt {
@@ -71,7 +486,10 @@ impl Parser {
                 let filename = if let Token::Identifier(s) = self.advance() { s } else { "lib".into() };
                 let path = format!("{}.hmr", filename);
                 match fs::read_to_string(&path) {
-                    Ok(content) => Stmt::MergeBlock(content),
+                    Ok(content) => {
+                        let (tokens, spans) = Lexer::new(content).tokenize_spanned();
+                        Stmt::MergeBlock(Parser { lib: true, ..Parser::new(tokens, spans) }.parse_program())
+                    }
                     Err(_) => Stmt::AsmBlock(format!("// Error: File not found {}.hmr", filename)),
                 }
             }
@@ -143,99 +561,301 @@ impl Parser {
             }
             Token::Local => {
                 self.advance();
+                let span = self.span();
                 let name = if let Token::Identifier(s) = self.advance() { s } else { "tmp".into() };
-                if self.peek() == Token::Assign { self.advance(); }
-                if self.peek() == Token::New {
+                let mut ty = None;
+                if self.peek() == Token::Colon {
                     self.advance();
-                    let cn = if let Token::Identifier(s) = self.advance() { s } else { "Object".into() };
-                    Stmt::HeapAlloc { var_name: name, class_name: cn }
+                    if let Token::Identifier(t) = self.advance() { ty = Some(t); }
+                }
+                if self.peek() == Token::Assign { self.advance(); }
+                if self.peek() == Token::New || self.peek() == Token::Stack {
+                    let on_stack = self.advance() == Token::Stack;
+                    let cn = match self.advance() {
+                        Token::Identifier(s) => s,
+                        Token::LeftBracket => {
+                            let (len, elem) = self.parse_array_type();
+                            format!("[{}]{}", len, elem)
+                        }
+                        _ => "Object".into(),
+                    };
+                    if on_stack {
+                        Stmt::StackAlloc { var_name: name, class_name: cn, span }
+                    } else {
+                        Stmt::HeapAlloc { var_name: name, class_name: cn, span }
+                    }
                 } else {
-                    let val = if let Token::Number(n) = self.advance() { n } else { 0.0 };
-                    Stmt::LocalAssign { name, value: val }
+                    Stmt::LocalAssign { name, ty, value: self.parse_expr(), span }
                 }
             }
+            Token::Arena => {
+                self.advance();
+                if self.peek() == Token::Is { self.advance(); }
+                let mut body = Vec::new();
+                while self.peek() != Token::Done && self.peek() != Token::EOF {
+                    body.push(self.parse_statement());
+                }
+                if self.peek() == Token::Done { self.advance(); }
+                Stmt::Arena(body)
+            }
+            Token::Free => {
+                let span = self.span();
+                self.advance();
+                Stmt::Free { value: self.parse_expr(), span }
+            }
             Token::Class => {
                 self.advance();
+                let span = self.span();
                 let name = if let Token::Identifier(s) = self.advance() { s } else { "Unnamed".into() };
+                let packed = self.peek() == Token::Identifier("packed".into());
+                if packed { self.advance(); }
                 if self.peek() == Token::Is { self.advance(); }
                 let mut fields = Vec::new();
                 while self.peek() != Token::Done && self.peek() != Token::EOF {
-                    if let Token::Identifier(s) = self.advance() { fields.push(s); }
-                    else { self.advance(); }
+                    let span = self.span();
+                    let Token::Identifier(name) = self.advance() else { continue };
+                    let mut field = Field { name, span, ty: None, offset: None, offset_const: None, len: None, len_const: None };
+                    if self.peek() == Token::Colon {
+                        self.advance();
+                        match self.advance() {
+                            Token::Identifier(t) => field.ty = Some(t),
+                            Token::LeftBracket => {
+                                let (len, elem) = self.parse_array_type();
+                                match len.parse() {
+                                    Ok(n) => field.len = Some(n),
+                                    Err(_) => field.len_const = Some(len),
+                                }
+                                field.ty = Some(elem);
+                            }
+                            _ => {}
+                        }
+                    }
+                    if self.peek() == Token::At {
+                        self.advance();
+                        match self.advance() {
+                            Token::Number(n) => field.offset = Some(n as usize),
+                            Token::Identifier(c) => field.offset_const = Some(c),
+                            _ => {}
+                        }
+                    }
+                    fields.push(field);
                 }
                 if self.peek() == Token::Done { self.advance(); }
-                Stmt::ClassDef { name, fields }
+                Stmt::ClassDef { name, fields, packed, span }
             }
-            Token::Print => {
+            Token::Const => {
                 self.advance();
-                match self.peek() {
-                    Token::StringLit(s) => {
+                let span = self.span();
+                let name = if let Token::Identifier(s) = self.advance() { s } else { String::new() };
+                if self.peek() == Token::Assign { self.advance(); }
+                Stmt::Const { name, value: self.parse_expr(), span }
+            }
+            Token::Global | Token::Export => {
+                let export = self.advance() == Token::Export;
+                if export && self.peek() == Token::Global { self.advance(); }
+                let span = self.span();
+                let name = if let Token::Identifier(s) = self.advance() { s } else { String::new() };
+                let mut ty = None;
+                if self.peek() == Token::Colon {
+                    self.advance();
+                    if let Token::Identifier(t) = self.advance() { ty = Some(t); }
+                }
+                let value = (self.peek() == Token::Assign).then(|| {
+                    self.advance();
+                    self.parse_expr()
+                });
+                Stmt::GlobalDef { name, ty, value, export, span }
+            }
+            Token::Enum => {
+                self.advance();
+                let span = self.span();
+                let name = if let Token::Identifier(s) = self.advance() { s } else { String::new() };
+                if self.peek() == Token::Is { self.advance(); }
+                let mut variants = Vec::new();
+                while self.peek() != Token::Done && self.peek() != Token::EOF {
+                    let span = self.span();
+                    let Token::Identifier(name) = self.advance() else { continue };
+                    let value = (self.peek() == Token::Assign).then(|| {
                         self.advance();
-                        Stmt::PrintString(s)
-                    },
-                    _ => {
-                        let path = self.parse_path();
-                        let name = path.get(0).cloned().unwrap_or("".into());
-                        Stmt::PrintVar(name)
-                    }
+                        self.parse_expr()
+                    });
+                    variants.push(Variant { name, value, span });
                 }
+                if self.peek() == Token::Done { self.advance(); }
+                Stmt::EnumDef { name, variants, span }
+            }
+            Token::Print | Token::Write | Token::EPrint => {
+                let span = self.span();
+                let (fd, newline) = match self.advance() {
+                    Token::Write => (1, false),
+                    Token::EPrint => (2, true),
+                    _ => (1, true),
+                };
+                let mut items = vec![self.parse_expr()];
+                while self.peek() == Token::Comma {
+                    self.advance();
+                    items.push(self.parse_expr());
+                }
+                Stmt::Print { items, fd, newline, span }
             }
             Token::If => {
+                let span = self.span();
                 self.advance();
                 if self.peek() == Token::Quest {
                     self.advance(); // ?
                     while matches!(self.peek(), Token::Less | Token::Percent) { self.advance(); }
-                    let chance = if let Token::Number(n) = self.advance() { n } else { 0.0 };
+                    let (chance, chance_const) = match self.advance() {
+                        Token::Number(n) => (n, None),
+                        Token::Identifier(c) => (0.0, Some(c)),
+                        _ => (0.0, None),
+                    };
+                    if self.peek() == Token::Greater { self.advance(); }
                     while matches!(self.peek(), Token::Then | Token::Is) { self.advance(); }
                     let mut body = Vec::new();
                     while self.peek() != Token::Done && self.peek() != Token::EOF {
                         body.push(self.parse_statement());
                     }
                     if self.peek() == Token::Done { self.advance(); }
-                    Stmt::ProbIf { chance, body }
+                    Stmt::ProbIf { chance, chance_const, body, span }
                 } else {
-                    let p = self.parse_path(); 
-                    let op = self.advance();
-                    let val = if let Token::Number(n) = self.advance() { n } else { 0.0 };
+                    let cond = self.parse_expr();
                     while matches!(self.peek(), Token::Then | Token::Is) { self.advance(); }
                     let mut body = Vec::new();
                     while self.peek() != Token::Done && self.peek() != Token::EOF {
                         body.push(self.parse_statement());
                     }
                     if self.peek() == Token::Done { self.advance(); }
-                    Stmt::IfStmt { path: p, op, rhs_val: val, body }
+                    Stmt::IfStmt { cond, body, span }
                 }
             }
             Token::While => {
+                let span = self.span();
                 self.advance();
-                let p = self.parse_path();
-                let op = self.advance();
-                let val = if let Token::Number(n) = self.advance() { n } else { 0.0 };
+                let cond = self.parse_expr();
                 while matches!(self.peek(), Token::Do | Token::Is) { self.advance(); }
                 let mut body = Vec::new();
                 while self.peek() != Token::Done && self.peek() != Token::EOF {
                     body.push(self.parse_statement());
                 }
                 if self.peek() == Token::Done { self.advance(); }
-                Stmt::WhileStmt { path: p, op, rhs_val: val, body }
+                Stmt::WhileStmt { cond, body, label: None, span }
             }
-            _ => {
-                let path = self.parse_path();
-                if self.peek() == Token::Assign {
+            Token::For => {
+                let span = self.span();
+                self.advance();
+                let var = if let Token::Identifier(n) = self.advance() { n } else { String::new() };
+                if self.peek() == Token::In { self.advance(); }
+                let start = self.parse_expr();
+                if self.peek() == Token::DotDot { self.advance(); }
+                let end = self.parse_expr();
+                let step = (self.peek() == Token::Identifier("step".into())).then(|| {
                     self.advance();
-                    if let Token::Number(v) = self.peek() {
+                    self.parse_expr()
+                });
+                while matches!(self.peek(), Token::Do | Token::Is) { self.advance(); }
+                let mut body = Vec::new();
+                while self.peek() != Token::Done && self.peek() != Token::EOF {
+                    body.push(self.parse_statement());
+                }
+                if self.peek() == Token::Done { self.advance(); }
+                Stmt::ForStmt { var, start, end, step, body, label: None, span }
+            }
+            Token::Match => {
+                let span = self.span();
+                self.advance();
+                let value = self.parse_expr();
+                if self.peek() == Token::Is { self.advance(); }
+                let mut arms = Vec::new();
+                while self.at_arm() {
+                    let span = self.span();
+                    let mut values = Vec::new();
+                    loop {
+                        if self.peek() == Token::Identifier("_".into()) {
+                            self.advance();
+                        } else {
+                            // Above `|`, which separates alternatives here.
+                            values.push(self.parse_binary(2));
+                        }
+                        if self.peek() != Token::Pipe { break; }
                         self.advance();
-                        Stmt::FieldAssign { path, value: v }
-                    } else {
-                        // Handle math like 'hp = hp + 10' or compressed formats
-                        self.advance(); // Skip self-ref identifier if exists
-                        let op = self.advance();
-                        let val = if let Token::Number(v) = self.advance() { v } else { 0.0 };
-                        Stmt::FieldMath { path, op, rhs_val: val }
                     }
+                    self.advance(); // =>
+                    let mut body = Vec::new();
+                    while !self.at_arm() && self.peek() != Token::Done && self.peek() != Token::EOF {
+                        body.push(self.parse_statement());
+                    }
+                    arms.push(MatchArm { values, body, span });
+                }
+                if self.peek() == Token::Done { self.advance(); }
+                Stmt::Match { value, arms, span }
+            }
+            Token::Exit => {
+                let span = self.span();
+                self.advance();
+                // Like a label, the status has to be on the same line.
+                let status = if self.pos < self.tokens.len() && self.span().line == span.line {
+                    self.parse_expr()
                 } else {
+                    Expr::Number(0.0)
+                };
+                Stmt::Exit { status, span }
+            }
+            Token::Break | Token::Continue => {
+                let span = self.span();
+                let brk = self.advance() == Token::Break;
+                // A label has to be on the same line, so the next statement isn't taken for one.
+                let label = match self.peek() {
+                    Token::Identifier(l) if self.span().line == span.line => { self.advance(); Some(l) }
+                    _ => None,
+                };
+                if brk { Stmt::Break { label, span } } else { Stmt::Continue { label, span } }
+            }
+            _ => {
+                let (start, span) = (self.pos, self.span());
+                // An expression alone on the program's last line is its exit status.
+                if !self.lib {
+                    let status = self.parse_expr();
+                    if self.peek() == Token::EOF && self.pos > start {
+                        return Stmt::Exit { status, span };
+                    }
+                    self.pos = start;
+                }
+                let path = self.parse_path();
+                if path.is_empty() {
                     self.advance(); // Safety: always consume at least one token
-                    Stmt::AsmBlock("nop".into())
+                    return Stmt::AsmBlock(String::new());
+                }
+                match self.peek() {
+                    // `outer: while ...` names a loop for `break outer`.
+                    Token::Colon if path.is_local() => {
+                        self.advance();
+                        let mut stmt = self.parse_statement();
+                        if let Stmt::WhileStmt { label, .. } | Stmt::ForStmt { label, .. } = &mut stmt {
+                            *label = Some(path.names[0].clone());
+                        }
+                        stmt
+                    }
+                    Token::Assign => {
+                        self.advance();
+                        match self.parse_expr() {
+                            // `hp = hp + 10` updates in place, like `hp += 10`. Indexed targets
+                            // are left alone, since `xs[i] = xs[j] + 1` reads another element.
+                            Expr::Binary { op, lhs, rhs }
+                                if !is_compare(&op) && path.index.is_none() && matches!(&*lhs, Expr::Path(p) if p.names == path.names && p.index.is_none()) =>
+                            {
+                                Stmt::FieldMath { path, op, rhs: *rhs }
+                            }
+                            value => Stmt::FieldAssign { path, value },
+                        }
+                    }
+                    Token::OpAssign(op) => {
+                        self.advance();
+                        Stmt::FieldMath { path, op: *op, rhs: self.parse_expr() }
+                    }
+                    _ => {
+                        self.advance(); // Safety: always consume at least one token
+                        Stmt::AsmBlock(String::new())
+                    }
                 }
             }
         }
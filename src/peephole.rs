@@ -0,0 +1,275 @@
+use std::collections::HashSet;
+use crate::runtime;
+
+/// A peephole rule rewrites `lines` in place and returns how many rewrites it made.
+pub struct Rule {
+    pub name: &'static str,
+    pub apply: fn(&mut Vec<String>) -> usize,
+}
+
+pub const RULES: &[Rule] = &[
+    Rule { name: "store-load", apply: store_load },
+    Rule { name: "mov-cmp", apply: mov_cmp },
+    Rule { name: "add-zero", apply: add_zero },
+    Rule { name: "print-spill", apply: print_spill },
+];
+
+/// Comments the generator puts around the body of an `@asm` block. Rules don't
+/// rewrite the lines between them.
+pub const ASM_START: &str = "// @asm";
+pub const ASM_END: &str = "// @asm done";
+
+/// Runs every enabled rule to a fixed point. Returns the cleaned assembly and
+/// the number of rewrites each rule made, in `RULES` order.
+pub fn optimize(asm: &str, disabled: &HashSet<String>) -> (String, Vec<(&'static str, usize)>) {
+    let mut lines: Vec<String> = asm.lines().map(String::from).collect();
+    let mut stats: Vec<(&'static str, usize)> = RULES.iter().map(|r| (r.name, 0)).collect();
+    loop {
+        let mut changed = 0;
+        for (i, rule) in RULES.iter().enumerate() {
+            if disabled.contains(rule.name) { continue; }
+            let n = (rule.apply)(&mut lines);
+            stats[i].1 += n;
+            changed += n;
+        }
+        if changed == 0 { break; }
+    }
+    let mut out = lines.join("\n");
+    out.push('\n');
+    (out, stats)
+}
+
+/// `ldr R, [B, #O]` right after `str R, [B, #O]` reloads the value it just stored.
+/// Only for x registers: a 32-bit reload zero-extends, so it isn't a no-op.
+fn store_load(lines: &mut Vec<String>) -> usize {
+    let mut n = 0;
+    let mut i = 0;
+    while let Some(j) = next_instr(lines, i + 1) {
+        let (op, args) = split(&lines[i]);
+        let (next_op, next_args) = split(&lines[j]);
+        if op == "str" && next_op == "ldr" && args == next_args && args.first().is_some_and(|r| r.starts_with('x'))
+            && !in_user_asm(lines, i) && !in_user_asm(lines, j)
+        {
+            lines.remove(j);
+            n += 1;
+        } else {
+            i = j;
+        }
+    }
+    n
+}
+
+/// `mov x1, reg; cmp x1, #imm` compares `reg` directly when x1 is dead afterwards.
+fn mov_cmp(lines: &mut Vec<String>) -> usize {
+    let mut n = 0;
+    let mut i = 0;
+    while let Some(j) = next_instr(lines, i + 1) {
+        let (op, args) = split(&lines[i]);
+        let (next_op, next_args) = split(&lines[j]);
+        if op == "mov" && next_op == "cmp" && !in_user_asm(lines, i) && !in_user_asm(lines, j) && args.len() == 2 && next_args.len() == 2
+            && args[0] == next_args[0] && reg_num(&args[1]).is_some() && next_args[1].starts_with('#')
+            && !live_after(lines, j + 1, &args[0], &mut HashSet::new())
+        {
+            lines[j] = format!("    cmp {}, {}", args[1], next_args[1]);
+            lines.remove(i);
+            n += 1;
+        } else {
+            i = j;
+        }
+    }
+    n
+}
+
+/// `add R, R, #0` and `sub R, R, #0` do nothing to an x register or sp. On a w
+/// register they clear the top half, so those stay.
+fn add_zero(lines: &mut Vec<String>) -> usize {
+    let mut n = 0;
+    let mut i = 0;
+    while i < lines.len() {
+        let (op, args) = split(&lines[i]);
+        if (op == "add" || op == "sub") && args.len() == 3 && args[0] == args[1] && args[2] == "#0"
+            && (args[0] == "sp" || args[0].starts_with('x')) && !in_user_asm(lines, i)
+        {
+            lines.remove(i);
+            n += 1;
+        } else {
+            i += 1;
+        }
+    }
+    n
+}
+
+/// Drops the `stp/ldp x0, x1` pair around a print when neither register is live after it.
+fn print_spill(lines: &mut Vec<String>) -> usize {
+    let mut n = 0;
+    let mut i = 0;
+    while i < lines.len() {
+        if lines[i].trim() != SPILL || in_user_asm(lines, i) { i += 1; continue; }
+        match matching_restore(lines, i) {
+            Some(k) if !in_user_asm(lines, k) && !live_after(lines, k + 1, "x0", &mut HashSet::new())
+                && !live_after(lines, k + 1, "x1", &mut HashSet::new()) =>
+            {
+                lines.remove(k);
+                lines.remove(i);
+                n += 1;
+            }
+            _ => i += 1,
+        }
+    }
+    n
+}
+
+const SPILL: &str = "stp x0, x1, [sp, #-16]!";
+const RESTORE: &str = "ldp x0, x1, [sp], #16";
+
+fn matching_restore(lines: &[String], spill: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (k, l) in lines.iter().enumerate().skip(spill + 1) {
+        match l.trim() {
+            SPILL => depth += 1,
+            RESTORE if depth == 0 => return Some(k),
+            RESTORE => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether line `i` is inside an `@asm` body.
+fn in_user_asm(lines: &[String], i: usize) -> bool {
+    lines[..i].iter().rev().map(|l| l.trim()).find(|t| *t == ASM_START || *t == ASM_END) == Some(ASM_START)
+}
+
+fn next_instr(lines: &[String], from: usize) -> Option<usize> {
+    (from..lines.len()).find(|&k| {
+        let t = lines[k].trim();
+        !t.is_empty() && !t.starts_with("//")
+    })
+}
+
+/// Splits `    op a, [b, #c]` into `op` and `["a", "[b, #c]"]`.
+fn split(line: &str) -> (String, Vec<String>) {
+    let t = line.trim();
+    if t.is_empty() || t.starts_with('.') || t.starts_with("//") || t.ends_with(':') {
+        return (String::new(), Vec::new());
+    }
+    let (op, rest) = t.split_once(' ').unwrap_or((t, ""));
+    let mut args = Vec::new();
+    let mut cur = String::new();
+    let mut depth = 0;
+    for c in rest.chars() {
+        match c {
+            '[' => { depth += 1; cur.push(c); }
+            ']' => { depth -= 1; cur.push(c); }
+            ',' if depth == 0 => { args.push(cur.trim().to_string()); cur.clear(); }
+            _ => cur.push(c),
+        }
+    }
+    if !cur.trim().is_empty() { args.push(cur.trim().to_string()); }
+    (op.to_string(), args)
+}
+
+/// `x7`/`w7` -> 7, anything else -> None.
+fn reg_num(s: &str) -> Option<u32> {
+    let s = s.trim_matches(|c| c == '[' || c == ']' || c == '!');
+    s.strip_prefix('x').or_else(|| s.strip_prefix('w')).and_then(|n| n.parse().ok())
+}
+
+fn mentions(args: &[String], reg: &str) -> bool {
+    let r = reg_num(reg);
+    args.iter().any(|a| a.split([',', ' ', '[', ']', '!'])
+        .any(|part| !part.is_empty() && reg_num(part).is_some() && reg_num(part) == r))
+}
+
+/// Whether `reg` may be read before being overwritten, starting at line `from`.
+/// Follows branches to their labels; anything it doesn't understand counts as a read.
+fn live_after(lines: &[String], from: usize, reg: &str, visited: &mut HashSet<usize>) -> bool {
+    let mut syscall = None;
+    let mut k = from;
+    while k < lines.len() {
+        let t = lines[k].trim();
+        k += 1;
+        if t.is_empty() || t.starts_with("//") || t.ends_with(':') { continue; }
+        if t.starts_with('.') {
+            // Only `.section` switches are expected in the instruction stream.
+            if t.starts_with(".section") || t.starts_with(".L") { continue; }
+            return true;
+        }
+        if t == SPILL {
+            // A print's save/restore pair hands x0/x1 back untouched, so look past it.
+            match matching_restore(lines, k - 1) {
+                Some(end) => { k = end + 1; continue; }
+                None => return true,
+            }
+        }
+        let (op, args) = split(t);
+        match op.as_str() {
+            "b" => {
+                return match args.first().and_then(|l| find_label(lines, l)) {
+                    Some(target) if visited.insert(target) => live_after(lines, target, reg, visited),
+                    Some(_) => false,
+                    None => true,
+                };
+            }
+            "cbz" | "cbnz" | "tbz" | "tbnz" => {
+                if mentions(&args[..1], reg) { return true; }
+                let label = args.last().cloned().unwrap_or_default();
+                if let Some(target) = find_label(lines, &label)
+                    && visited.insert(target)
+                    && live_after(lines, target, reg, visited)
+                {
+                    return true;
+                }
+            }
+            _ if op.starts_with("b.") => {
+                if let Some(target) = args.first().and_then(|l| find_label(lines, l))
+                    && visited.insert(target)
+                    && live_after(lines, target, reg, visited)
+                {
+                    return true;
+                }
+            }
+            "bl" => {
+                // Runtime routines read their argument registers and clobber x0..x8.
+                let n = reg_num(reg).unwrap_or(0);
+                match args.first().and_then(|name| runtime::arity(name)) {
+                    Some(arity) if (n as usize) < arity => return true,
+                    Some(_) if n <= 8 => return false,
+                    Some(_) => {}
+                    None => return true,
+                }
+            }
+            "svc" => {
+                let n = reg_num(reg).unwrap_or(0);
+                match syscall {
+                    Some(93) | Some(94) => return n == 0,
+                    Some(_) if n > 5 => {}
+                    _ => return true,
+                }
+            }
+            "str" | "strb" | "strh" | "stp" | "cmp" | "cmn" | "tst" | "fcmp" => {
+                if mentions(&args, reg) { return true; }
+            }
+            "ldp" => {
+                if mentions(&args[2..], reg) { return true; }
+                if mentions(&args[..2], reg) { return false; }
+            }
+            "mov" | "add" | "sub" | "mul" | "udiv" | "sdiv" | "msub" | "madd" | "eor" | "and" | "orr"
+            | "lsl" | "lsr" | "asr" | "ldr" | "ldrb" | "ldrh" | "ldrsb" | "ldrsh" | "ldrsw" | "mrs" | "adr" | "adrp"
+            | "neg" | "cset" | "csel" | "cneg" | "mvn" => {
+                if args.is_empty() { return true; }
+                if op == "mov" && args[0] == "x8" { syscall = args.get(1).and_then(|v| v.trim_start_matches('#').parse().ok()); }
+                if mentions(&args[1..], reg) { return true; }
+                // A w-register write zeroes the top half, so it kills the x-register too.
+                if mentions(&args[..1], reg) { return false; }
+            }
+            _ => return true,
+        }
+    }
+    false
+}
+
+fn find_label(lines: &[String], label: &str) -> Option<usize> {
+    let want = format!("{}:", label);
+    lines.iter().position(|l| l.trim() == want).map(|p| p + 1)
+}
@@ -0,0 +1,272 @@
+use std::collections::{HashMap, HashSet};
+use crate::layout;
+use crate::lexer::Span;
+use crate::parser::{Expr, Path, Stmt};
+
+/// Functions every program can call.
+const BUILTINS: [&str; 3] = ["len", "str", "int"];
+
+struct Resolver {
+    /// Classes defined so far, with where each one and each of its fields was written.
+    classes: HashMap<String, (Span, Vec<(String, Span)>)>,
+    /// Locals in scope, with the class of the ones that hold objects and where they were declared.
+    locals: HashMap<String, (Option<String>, Span)>,
+    /// Locals declared by the innermost block; a `local` of any other name in scope shadows it.
+    scope: HashSet<String>,
+    /// The first declaration of every local in the program, to tell a use that comes
+    /// too early from one of a name that doesn't exist.
+    declared: HashMap<String, Span>,
+    /// Labels of the loops around the current statement, innermost last.
+    loops: Vec<Option<String>>,
+    /// Globals with where they were declared; they live at the top level only.
+    globals: HashMap<String, Span>,
+    /// How many blocks the current statement is nested in.
+    depth: usize,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+/// Checks that every variable, field and class a program names is defined before it's used.
+/// Returns errors and warnings, each starting with the `line:col` it points at.
+pub fn resolve(ast: &[Stmt]) -> (Vec<String>, Vec<String>) {
+    let mut r = Resolver {
+        classes: HashMap::new(),
+        locals: HashMap::new(),
+        scope: HashSet::new(),
+        declared: HashMap::new(),
+        loops: Vec::new(),
+        globals: HashMap::new(),
+        depth: 0,
+        errors: Vec::new(),
+        warnings: Vec::new(),
+    };
+    collect_decls(ast, &mut r.declared);
+    for s in ast { r.stmt(s); }
+    (r.errors, r.warnings)
+}
+
+fn collect_decls(stmts: &[Stmt], out: &mut HashMap<String, Span>) {
+    for s in stmts {
+        match s {
+            Stmt::LocalAssign { name, span, .. }
+            | Stmt::GlobalDef { name, span, .. }
+            | Stmt::HeapAlloc { var_name: name, span, .. }
+            | Stmt::StackAlloc { var_name: name, span, .. } => {
+                out.entry(name.clone()).or_insert(*span);
+            }
+            Stmt::ForStmt { var, span, body, .. } => {
+                out.entry(var.clone()).or_insert(*span);
+                collect_decls(body, out);
+            }
+            Stmt::Match { arms, .. } => for a in arms { collect_decls(&a.body, out) },
+            Stmt::IfStmt { body, .. } | Stmt::WhileStmt { body, .. } | Stmt::ProbIf { body, .. }
+            | Stmt::Arena(body) | Stmt::MergeBlock(body) => collect_decls(body, out),
+            _ => {}
+        }
+    }
+}
+
+impl Resolver {
+    /// Resolves a nested block; its locals go out of scope at its `done`.
+    fn block(&mut self, stmts: &[Stmt]) {
+        let locals = self.locals.clone();
+        let scope = std::mem::take(&mut self.scope);
+        self.depth += 1;
+        for s in stmts { self.stmt(s); }
+        self.depth -= 1;
+        self.locals = locals;
+        self.scope = scope;
+    }
+
+    fn declare(&mut self, name: &str, class: Option<String>, span: Span) {
+        if self.depth == 0
+            && let Some(global) = self.globals.get(name)
+        {
+            self.errors.push(format!("{}: `{}` is already a global declared at {}", span, name, global));
+        }
+        if !self.scope.contains(name)
+            && let Some((_, outer)) = self.locals.get(name)
+        {
+            let kind = if self.globals.contains_key(name) { "global" } else { "local" };
+            self.warnings.push(format!("{}: `{}` shadows the {} declared at {}", span, name, kind, outer));
+        }
+        self.scope.insert(name.to_string());
+        self.locals.insert(name.to_string(), (class, span));
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::ClassDef { name, fields, span, .. } => {
+                if let Some((prev, _)) = self.classes.get(name) {
+                    self.errors.push(format!("{}: class `{}` is already defined at {}", span, name, prev));
+                    return;
+                }
+                let mut seen: Vec<(String, Span)> = Vec::new();
+                for f in fields {
+                    if let Some((_, prev)) = seen.iter().find(|(n, _)| n == &f.name) {
+                        self.errors.push(format!("{}: field `{}` is already defined in `{}` at {}", f.span, f.name, name, prev));
+                        continue;
+                    }
+                    seen.push((f.name.clone(), f.span));
+                }
+                self.classes.insert(name.clone(), (*span, seen));
+            }
+            Stmt::GlobalDef { name, value, span, .. } => {
+                if let Some(v) = value { self.expr(v); }
+                if self.depth > 0 {
+                    self.errors.push(format!("{}: `global {}` has to be at the top level, outside any block", span, name));
+                    return;
+                }
+                if let Some((_, prev)) = self.locals.get(name).filter(|_| self.scope.contains(name)) {
+                    self.errors.push(format!("{}: `{}` is already defined at {}", span, name, prev));
+                    return;
+                }
+                self.declare(name, None, *span);
+                self.globals.insert(name.clone(), *span);
+            }
+            Stmt::LocalAssign { name, value, span, .. } => {
+                self.expr(value);
+                // A copy of an object reference keeps its class.
+                let class = match value {
+                    Expr::Path(p) if p.is_local() => self.locals.get(&p[0]).and_then(|(c, _)| c.clone()),
+                    _ => None,
+                };
+                self.declare(name, class, *span);
+            }
+            Stmt::HeapAlloc { var_name, class_name, span } | Stmt::StackAlloc { var_name, class_name, span } => {
+                if !self.classes.contains_key(class_name) && layout::array_type(class_name).is_none() {
+                    let hint = suggest(class_name, self.classes.keys());
+                    self.errors.push(format!("{}: unknown class `{}`{}", span, class_name, hint));
+                }
+                self.declare(var_name, Some(class_name.clone()), *span);
+            }
+            Stmt::FieldAssign { path, value: e } | Stmt::FieldMath { path, rhs: e, .. } => {
+                self.expr(e);
+                self.path(path);
+            }
+            Stmt::IfStmt { cond, body, .. } => {
+                self.expr(cond);
+                self.block(body);
+            }
+            Stmt::WhileStmt { cond, body, label, .. } => {
+                self.expr(cond);
+                self.loops.push(label.clone());
+                self.block(body);
+                self.loops.pop();
+            }
+            Stmt::ForStmt { var, start, end, step, body, label, span } => {
+                self.expr(start);
+                self.expr(end);
+                if let Some(step) = step { self.expr(step); }
+                // The loop variable lives in a scope of its own around the body.
+                let locals = self.locals.clone();
+                let scope = std::mem::take(&mut self.scope);
+                self.declare(var, None, *span);
+                self.loops.push(label.clone());
+                self.block(body);
+                self.loops.pop();
+                self.locals = locals;
+                self.scope = scope;
+            }
+            Stmt::Match { value, arms, .. } => {
+                self.expr(value);
+                for (i, arm) in arms.iter().enumerate() {
+                    if arm.values.is_empty() && i + 1 < arms.len() {
+                        self.errors.push(format!("{}: the `_` arm has to come last; the arms after it never run", arm.span));
+                    }
+                    for v in &arm.values { self.expr(v); }
+                    self.block(&arm.body);
+                }
+            }
+            Stmt::Break { label, span } | Stmt::Continue { label, span } => {
+                let what = if matches!(stmt, Stmt::Break { .. }) { "break" } else { "continue" };
+                if self.loops.is_empty() {
+                    self.errors.push(format!("{}: `{}` outside of a loop", span, what));
+                } else if let Some(l) = label
+                    && !self.loops.iter().any(|n| n.as_ref() == Some(l))
+                {
+                    let hint = suggest(l, self.loops.iter().flatten());
+                    self.errors.push(format!("{}: no enclosing loop is labelled `{}`{}", span, l, hint));
+                }
+            }
+            Stmt::Print { items, .. } => {
+                for e in items { self.expr(e); }
+            }
+            Stmt::Free { value: e, .. } | Stmt::Exit { status: e, .. } => self.expr(e),
+            Stmt::ProbIf { body, .. } | Stmt::Arena(body) => self.block(body),
+            // Merged libraries share the scope of the program they are merged into.
+            Stmt::MergeBlock(body) => for s in body { self.stmt(s) },
+            // Their uses were folded into numbers by `consts::fold`.
+            Stmt::Const { .. } | Stmt::EnumDef { .. } => {}
+            Stmt::AsmBlock(_) | Stmt::IntelBlock(_) | Stmt::PythonBlock(_) => {}
+        }
+    }
+
+    fn expr(&mut self, e: &Expr) {
+        match e {
+            Expr::Path(p) => self.path(p),
+            Expr::Neg(e) | Expr::Format { value: e, .. } => self.expr(e),
+            Expr::Interp(parts) => for p in parts { self.expr(p) },
+            Expr::Binary { lhs, rhs, .. } => { self.expr(lhs); self.expr(rhs); }
+            Expr::Call { name, args, span } => {
+                if !BUILTINS.contains(&name.as_str()) {
+                    let hint = suggest(name, BUILTINS.map(String::from).iter());
+                    self.errors.push(format!("{}: unknown function `{}`{}", span, name, hint));
+                }
+                for a in args { self.expr(a); }
+            }
+            Expr::Slice { value, start, end } => {
+                self.expr(value);
+                for bound in [start, end].into_iter().flatten() { self.expr(bound); }
+            }
+            Expr::Number(_) | Expr::Str(_) | Expr::Bool(_) => {}
+        }
+    }
+
+    fn path(&mut self, path: &Path) {
+        if let Some(index) = &path.index { self.expr(index); }
+        let base = &path[0];
+        let Some((class, _)) = self.locals.get(base) else {
+            let msg = match self.declared.get(base) {
+                Some(decl) if *decl < path.span => format!("`{}` is not in scope; it was declared in a block at {}", base, decl),
+                Some(decl) => format!("`{}` is used before its declaration at {}", base, decl),
+                None => format!("undefined variable `{}`{}", base, suggest(base, self.locals.keys())),
+            };
+            self.errors.push(format!("{}: {}", path.span, msg));
+            return;
+        };
+        if path.len() > 1
+            && let Some(class) = class
+            && let Some((_, fields)) = self.classes.get(class)
+            && !fields.iter().any(|(f, _)| f == &path[1])
+        {
+            let hint = suggest(&path[1], fields.iter().map(|(f, _)| f));
+            self.errors.push(format!("{}: `{}` has no field `{}`{}", path.span, class, path[1], hint));
+        }
+    }
+}
+
+/// `; did you mean `x`?` for the candidate closest to `name`, if any is close enough.
+pub fn suggest<'a>(name: &str, candidates: impl Iterator<Item = &'a String>) -> String {
+    candidates
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= (name.len() / 3).max(1))
+        .min()
+        .map(|(_, c)| format!("; did you mean `{}`?", c))
+        .unwrap_or_default()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let next = (diag + (ca != *cb) as usize).min(row[j] + 1).min(row[j + 1] + 1);
+            diag = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
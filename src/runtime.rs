@@ -0,0 +1,984 @@
+/// A shared routine, emitted once after the program and reached with `bl`.
+/// Arguments go in x0..x2 (d0 for a float) and results come back in x0. A routine may
+/// clobber x0..x8, d0..d7 and x30; every register from x9 and d8 up survives the call.
+pub struct Routine {
+    pub name: &'static str,
+    pub args: usize,
+    pub deps: &'static [&'static str],
+    pub body: &'static str,
+}
+
+pub const ROUTINES: &[Routine] = &[
+    Routine {
+        // x0 = value, x1 = spec word, x2 = fd. The spec word packs the base in bits 0-7,
+        // then signed (8), force '+' (9), zero-pad (10), upper-case hex (11),
+        // width in bits 16-23 (at most 64) and a byte to append in bits 24-31 (0 for none).
+        name: "__hamer_format",
+        args: 3,
+        deps: &[],
+        body: "
+    sub sp, sp, #112
+    str x2, [sp]
+    add x6, sp, #112
+    mov x4, x6
+    ubfx x3, x1, #24, #8
+    cbz x3, .Lrt_fmt_sign
+    sub x4, x4, #1
+    strb w3, [x4]
+.Lrt_fmt_sign:
+    mov x5, #0
+    tbz x1, #8, .Lrt_fmt_digits
+    tbz x0, #63, .Lrt_fmt_positive
+    neg x0, x0
+    mov x5, #45
+    b .Lrt_fmt_digits
+.Lrt_fmt_positive:
+    tbz x1, #9, .Lrt_fmt_digits
+    mov x5, #43
+.Lrt_fmt_digits:
+    and x3, x1, #0xff
+    mov x2, x4
+.Lrt_fmt_digit:
+    udiv x7, x0, x3
+    msub x8, x7, x3, x0
+    add x8, x8, #48
+    cmp x8, #57
+    b.ls .Lrt_fmt_store
+    add x8, x8, #39
+    tbz x1, #11, .Lrt_fmt_store
+    sub x8, x8, #32
+.Lrt_fmt_store:
+    sub x4, x4, #1
+    strb w8, [x4]
+    mov x0, x7
+    cbnz x0, .Lrt_fmt_digit
+    sub x7, x2, x4
+    cmp x5, #0
+    cinc x7, x7, ne
+    ubfx x8, x1, #16, #8
+    subs x7, x8, x7
+    b.le .Lrt_fmt_put_sign
+    mov w8, #48
+    tbnz x1, #10, .Lrt_fmt_pad
+    cbz x5, .Lrt_fmt_spaces
+    sub x4, x4, #1
+    strb w5, [x4]
+    mov x5, #0
+.Lrt_fmt_spaces:
+    mov w8, #32
+.Lrt_fmt_pad:
+    sub x4, x4, #1
+    strb w8, [x4]
+    subs x7, x7, #1
+    b.ne .Lrt_fmt_pad
+.Lrt_fmt_put_sign:
+    cbz x5, .Lrt_fmt_write
+    sub x4, x4, #1
+    strb w5, [x4]
+.Lrt_fmt_write:
+    ldr x0, [sp]
+    mov x1, x4
+    sub x2, x6, x4
+    mov x8, #64
+    svc #0
+    add sp, sp, #112
+    ret
+",
+    },
+    Routine {
+        // x0 = value, x1 = byte to append (0 for none), x2 = fd.
+        name: "__hamer_print_u64",
+        args: 3,
+        deps: &["__hamer_format"],
+        body: "
+    lsl x1, x1, #24
+    add x1, x1, #10
+    b __hamer_format
+",
+    },
+    Routine {
+        name: "__hamer_print_i64",
+        args: 3,
+        deps: &["__hamer_format"],
+        body: "
+    lsl x1, x1, #24
+    add x1, x1, #266
+    b __hamer_format
+",
+    },
+    Routine {
+        // x0 = address, x1 = length, x2 = fd.
+        name: "__hamer_print_str",
+        args: 3,
+        deps: &[],
+        body: "
+    mov x3, x1
+    mov x1, x0
+    mov x0, x2
+    mov x2, x3
+    mov x8, #64
+    svc #0
+    ret
+",
+    },
+    Routine {
+        // d0 = value, x1 = spec word, x2 = fd. The spec word packs the number of fraction
+        // digits in bits 0-7 (at most 18), force '+' in bit 9 and a byte to append in bits 24-31.
+        // Fractions are rounded to nearest and the rounding carries into the integer part.
+        name: "__hamer_print_f64",
+        args: 3,
+        deps: &[],
+        body: "
+    sub sp, sp, #80
+    str x2, [sp]
+    add x6, sp, #80
+    mov x4, x6
+    ubfx x3, x1, #24, #8
+    cbz x3, .Lrt_f64_sign
+    sub x4, x4, #1
+    strb w3, [x4]
+.Lrt_f64_sign:
+    mov x5, #0
+    fcmp d0, #0.0
+    b.pl .Lrt_f64_positive
+    fneg d0, d0
+    mov x5, #45
+    b .Lrt_f64_split
+.Lrt_f64_positive:
+    tbz x1, #9, .Lrt_f64_split
+    mov x5, #43
+.Lrt_f64_split:
+    and x1, x1, #0xff
+    fcvtzu x2, d0
+    ucvtf d1, x2
+    fsub d1, d0, d1
+    mov x7, #10
+    mov x3, #1
+    mov x8, x1
+.Lrt_f64_scale:
+    cbz x8, .Lrt_f64_round
+    mul x3, x3, x7
+    sub x8, x8, #1
+    b .Lrt_f64_scale
+.Lrt_f64_round:
+    ucvtf d2, x3
+    fmul d1, d1, d2
+    fcvtau x0, d1
+    cmp x0, x3
+    b.lo .Lrt_f64_frac
+    sub x0, x0, x3
+    add x2, x2, #1
+.Lrt_f64_frac:
+    cbz x1, .Lrt_f64_int
+.Lrt_f64_frac_digit:
+    udiv x3, x0, x7
+    msub x8, x3, x7, x0
+    add x8, x8, #48
+    sub x4, x4, #1
+    strb w8, [x4]
+    mov x0, x3
+    subs x1, x1, #1
+    b.ne .Lrt_f64_frac_digit
+    mov w8, #46
+    sub x4, x4, #1
+    strb w8, [x4]
+.Lrt_f64_int:
+    udiv x3, x2, x7
+    msub x8, x3, x7, x2
+    add x8, x8, #48
+    sub x4, x4, #1
+    strb w8, [x4]
+    mov x2, x3
+    cbnz x2, .Lrt_f64_int
+    cbz x5, .Lrt_f64_write
+    sub x4, x4, #1
+    strb w5, [x4]
+.Lrt_f64_write:
+    ldr x0, [sp]
+    mov x1, x4
+    sub x2, x6, x4
+    mov x8, #64
+    svc #0
+    add sp, sp, #80
+    ret
+",
+    },
+    Routine {
+        // x0 = size in bytes, rounded up to 8 (at least 8). Every block carries its size
+        // in the 8 bytes before the returned pointer. Blocks up to 1 KiB come from the
+        // free list for their size, then from the x20 bump chunk, which grows by
+        // max(size, __hamer_heap_chunk) when it runs out. Bigger blocks get their own mapping.
+        // Each mapping starts with a link to the one made before it and its length, from
+        // __hamer_maps, so an arena can unmap the ones made inside it.
+        name: "__hamer_alloc",
+        args: 1,
+        deps: &["__hamer_oom"],
+        body: "
+    add x0, x0, #7
+    and x0, x0, #-8
+    mov x1, #8
+    cmp x0, #0
+    csel x0, x1, x0, eq
+    cmp x0, #1024
+    b.hi .Lrt_alloc_large
+    adrp x2, __hamer_free_lists
+    add x2, x2, :lo12:__hamer_free_lists
+    lsr x3, x0, #3
+    sub x3, x3, #1
+    ldr x1, [x2, x3, lsl #3]
+    cbz x1, .Lrt_alloc_bump
+    ldr x4, [x1]
+    str x4, [x2, x3, lsl #3]
+    mov x4, #0
+.Lrt_alloc_zero:
+    str xzr, [x1, x4]
+    add x4, x4, #8
+    cmp x4, x0
+    b.lo .Lrt_alloc_zero
+    mov x0, x1
+    ret
+.Lrt_alloc_bump:
+    add x7, x0, #8
+    adrp x1, __hamer_heap_end
+    ldr x2, [x1, :lo12:__hamer_heap_end]
+    add x3, x20, x7
+    cmp x3, x2
+    b.hi .Lrt_alloc_grow
+    str x0, [x20]
+    add x0, x20, #8
+    mov x20, x3
+    ret
+.Lrt_alloc_grow:
+    add x7, x7, #16
+    adrp x1, __hamer_heap_chunk
+    ldr x6, [x1, :lo12:__hamer_heap_chunk]
+    cmp x7, x6
+    csel x6, x7, x6, hi
+    mov x0, #0
+    mov x1, x6
+    mov x2, #3
+    mov x3, #34
+    mov x4, #-1
+    mov x5, #0
+    mov x8, #222
+    svc #0
+    cmn x0, #4096
+    b.hi __hamer_oom
+    adrp x2, __hamer_maps
+    ldr x3, [x2, :lo12:__hamer_maps]
+    stp x3, x6, [x0]
+    str x0, [x2, :lo12:__hamer_maps]
+    add x1, x0, x6
+    adrp x2, __hamer_heap_end
+    str x1, [x2, :lo12:__hamer_heap_end]
+    sub x2, x7, #24
+    str x2, [x0, #16]
+    add x20, x0, x7
+    add x0, x0, #24
+    ret
+.Lrt_alloc_large:
+    mov x7, x0
+    mov x0, #0
+    add x1, x7, #24
+    mov x2, #3
+    mov x3, #34
+    mov x4, #-1
+    mov x5, #0
+    mov x8, #222
+    svc #0
+    cmn x0, #4096
+    b.hi __hamer_oom
+    adrp x2, __hamer_maps
+    ldr x3, [x2, :lo12:__hamer_maps]
+    add x4, x7, #24
+    stp x3, x4, [x0]
+    str x0, [x2, :lo12:__hamer_maps]
+    str x7, [x0, #16]
+    add x0, x0, #24
+    ret
+.section .bss
+.balign 8
+__hamer_free_lists: .skip 1024
+.section .text
+",
+    },
+    Routine {
+        // x0 = block from __hamer_alloc, or 0. Small blocks go back on the free list
+        // for their size; big ones are returned to the kernel with munmap. A big block
+        // stays linked into __hamer_maps, which only arenas walk, and a block made inside
+        // an arena can't be freed.
+        name: "__hamer_free",
+        args: 1,
+        deps: &["__hamer_alloc"],
+        body: "
+    cbz x0, .Lrt_free_done
+    ldr x1, [x0, #-8]
+    cmp x1, #1024
+    b.hi .Lrt_free_large
+    adrp x2, __hamer_free_lists
+    add x2, x2, :lo12:__hamer_free_lists
+    lsr x3, x1, #3
+    sub x3, x3, #1
+    ldr x4, [x2, x3, lsl #3]
+    str x4, [x0]
+    str x0, [x2, x3, lsl #3]
+.Lrt_free_done:
+    ret
+.Lrt_free_large:
+    sub x0, x0, #24
+    add x1, x1, #24
+    mov x8, #215
+    svc #0
+    ret
+",
+    },
+    Routine {
+        // x0 = 1056-byte save area: x20, __hamer_heap_end, __hamer_maps, a spare word
+        // and the 128 free-list heads.
+        // The lists start empty inside the arena, so it never writes over a block that
+        // is still linked into a list it will restore.
+        name: "__hamer_arena_enter",
+        args: 1,
+        deps: &["__hamer_alloc"],
+        body: "
+    adrp x1, __hamer_heap_end
+    ldr x1, [x1, :lo12:__hamer_heap_end]
+    stp x20, x1, [x0], #16
+    adrp x1, __hamer_maps
+    ldr x1, [x1, :lo12:__hamer_maps]
+    stp x1, xzr, [x0], #16
+    adrp x1, __hamer_free_lists
+    add x1, x1, :lo12:__hamer_free_lists
+    mov x2, #128
+.Lrt_arena_save:
+    ldr x3, [x1]
+    str xzr, [x1], #8
+    str x3, [x0], #8
+    subs x2, x2, #1
+    b.ne .Lrt_arena_save
+    ret
+",
+    },
+    Routine {
+        // x0 = save area from __hamer_arena_enter.
+        name: "__hamer_arena_leave",
+        args: 1,
+        deps: &["__hamer_alloc", "__hamer_arena_rewind"],
+        body: "
+    ldp x4, x5, [x0], #16
+    ldr x6, [x0], #16
+    adrp x1, __hamer_free_lists
+    add x1, x1, :lo12:__hamer_free_lists
+    mov x2, #128
+.Lrt_arena_restore:
+    ldr x3, [x0], #8
+    str x3, [x1], #8
+    subs x2, x2, #1
+    b.ne .Lrt_arena_restore
+    mov x0, x4
+    mov x1, x5
+    mov x2, x6
+    b __hamer_arena_rewind
+",
+    },
+    Routine {
+        // x0 = bump pointer, x1 = chunk end and x2 = __hamer_maps saved when the arena was
+        // entered. Unmaps the chunks and big blocks mapped since, zeroes what the arena handed
+        // out of the old chunk, so the next `new` there starts from zero like a fresh mapping,
+        // then moves x20 back. If the arena grew into a new chunk, its objects in the old one
+        // run up to the old chunk's end.
+        name: "__hamer_arena_rewind",
+        args: 3,
+        deps: &[],
+        body: "
+    mov x6, x0
+    mov x7, x1
+    adrp x5, __hamer_maps
+    ldr x3, [x5, :lo12:__hamer_maps]
+.Lrt_rewind_unmap:
+    cmp x3, x2
+    b.eq .Lrt_rewind_unmapped
+    ldp x4, x1, [x3]
+    mov x0, x3
+    mov x8, #215
+    svc #0
+    mov x3, x4
+    b .Lrt_rewind_unmap
+.Lrt_rewind_unmapped:
+    str x2, [x5, :lo12:__hamer_maps]
+    mov x0, x6
+    mov x1, x7
+    mov x2, x1
+    cmp x20, x0
+    b.lo .Lrt_rewind_zero
+    cmp x20, x1
+    csel x2, x20, x1, ls
+.Lrt_rewind_zero:
+    mov x3, x0
+.Lrt_rewind_loop:
+    cmp x3, x2
+    b.hs .Lrt_rewind_done
+    str xzr, [x3], #8
+    b .Lrt_rewind_loop
+.Lrt_rewind_done:
+    mov x20, x0
+    adrp x2, __hamer_heap_end
+    str x1, [x2, :lo12:__hamer_heap_end]
+    ret
+",
+    },
+    Routine {
+        // x0 = payload size, x1 = type descriptor. Used under --gc instead of __hamer_alloc.
+        // The object gets a 16-byte header in front: [p-16] links all live objects (bit 0 is
+        // the mark, bit 1 \"fields scanned\"), [p-8] points at the descriptor. A collection
+        // runs first once a heap chunk's worth of bytes has been handed out since the last one.
+        name: "__hamer_gc_alloc",
+        args: 2,
+        deps: &["__hamer_alloc", "__hamer_gc_collect"],
+        body: "
+    stp x29, x30, [sp, #-16]!
+    stp x0, x1, [sp, #-16]!
+    adrp x2, __hamer_gc_bytes
+    ldr x3, [x2, :lo12:__hamer_gc_bytes]
+    add x3, x3, x0
+    add x3, x3, #16
+    str x3, [x2, :lo12:__hamer_gc_bytes]
+    adrp x2, __hamer_heap_chunk
+    ldr x4, [x2, :lo12:__hamer_heap_chunk]
+    cmp x3, x4
+    b.lo .Lrt_gc_alloc_take
+    bl __hamer_gc_collect
+.Lrt_gc_alloc_take:
+    ldr x0, [sp]
+    add x0, x0, #16
+    bl __hamer_alloc
+    ldp x1, x2, [sp], #16
+    adrp x3, __hamer_gc_objects
+    ldr x4, [x3, :lo12:__hamer_gc_objects]
+    str x4, [x0]
+    str x2, [x0, #8]
+    add x0, x0, #16
+    str x0, [x3, :lo12:__hamer_gc_objects]
+    ldp x29, x30, [sp], #16
+    ret
+",
+    },
+    Routine {
+        // Conservative mark-sweep. x9..x28 are pushed so the stack scan covers every
+        // register root; any word equal to a live object's address marks it. Marked
+        // objects then have their reference fields scanned until nothing new is marked,
+        // and unmarked objects go back through __hamer_free.
+        name: "__hamer_gc_collect",
+        args: 0,
+        deps: &["__hamer_free"],
+        body: "
+    stp x29, x30, [sp, #-16]!
+    stp x9, x10, [sp, #-16]!
+    stp x11, x12, [sp, #-16]!
+    stp x13, x14, [sp, #-16]!
+    stp x15, x16, [sp, #-16]!
+    stp x17, x18, [sp, #-16]!
+    stp x19, x20, [sp, #-16]!
+    stp x21, x22, [sp, #-16]!
+    stp x23, x24, [sp, #-16]!
+    stp x25, x26, [sp, #-16]!
+    stp x27, x28, [sp, #-16]!
+    mov x9, sp
+    adrp x10, __hamer_stack_base
+    ldr x10, [x10, :lo12:__hamer_stack_base]
+.Lrt_gc_roots:
+    cmp x9, x10
+    b.hs .Lrt_gc_trace
+    ldr x0, [x9], #8
+    bl .Lrt_gc_mark
+    b .Lrt_gc_roots
+.Lrt_gc_trace:
+    mov x11, #0
+    adrp x9, __hamer_gc_objects
+    ldr x9, [x9, :lo12:__hamer_gc_objects]
+.Lrt_gc_trace_obj:
+    cbz x9, .Lrt_gc_trace_pass
+    ldr x12, [x9, #-16]
+    tbz x12, #0, .Lrt_gc_trace_next
+    tbnz x12, #1, .Lrt_gc_trace_next
+    orr x12, x12, #2
+    str x12, [x9, #-16]
+    mov x11, #1
+    ldr x13, [x9, #-8]
+    ldr x14, [x13]
+    ldr x15, [x13, #8]
+    mov x16, #0
+.Lrt_gc_field:
+    cmp x16, x14
+    b.hs .Lrt_gc_trace_next
+    mov x17, #63
+    cmp x16, #63
+    csel x17, x16, x17, lo
+    lsr x17, x15, x17
+    tbz x17, #0, .Lrt_gc_field_next
+    ldr x0, [x9, x16, lsl #3]
+    bl .Lrt_gc_mark
+.Lrt_gc_field_next:
+    add x16, x16, #1
+    b .Lrt_gc_field
+.Lrt_gc_trace_next:
+    and x9, x12, #-4
+    b .Lrt_gc_trace_obj
+.Lrt_gc_trace_pass:
+    cbnz x11, .Lrt_gc_trace
+    adrp x9, __hamer_gc_objects
+    add x9, x9, :lo12:__hamer_gc_objects
+.Lrt_gc_sweep:
+    ldr x10, [x9]
+    and x10, x10, #-4
+    cbz x10, .Lrt_gc_done
+    ldr x11, [x10, #-16]
+    and x12, x11, #-4
+    tbnz x11, #0, .Lrt_gc_keep
+    str x12, [x9]
+    sub x0, x10, #16
+    bl __hamer_free
+    b .Lrt_gc_sweep
+.Lrt_gc_keep:
+    str x12, [x10, #-16]
+    sub x9, x10, #16
+    b .Lrt_gc_sweep
+.Lrt_gc_done:
+    adrp x9, __hamer_gc_bytes
+    str xzr, [x9, :lo12:__hamer_gc_bytes]
+    ldp x27, x28, [sp], #16
+    ldp x25, x26, [sp], #16
+    ldp x23, x24, [sp], #16
+    ldp x21, x22, [sp], #16
+    ldp x19, x20, [sp], #16
+    ldp x17, x18, [sp], #16
+    ldp x15, x16, [sp], #16
+    ldp x13, x14, [sp], #16
+    ldp x11, x12, [sp], #16
+    ldp x9, x10, [sp], #16
+    ldp x29, x30, [sp], #16
+    ret
+// x0 = candidate word: marks the object it points at, if any. Clobbers x1.
+.Lrt_gc_mark:
+    adrp x1, __hamer_gc_objects
+    ldr x1, [x1, :lo12:__hamer_gc_objects]
+.Lrt_gc_mark_find:
+    cbz x1, .Lrt_gc_mark_done
+    cmp x1, x0
+    b.eq .Lrt_gc_mark_set
+    ldr x1, [x1, #-16]
+    and x1, x1, #-4
+    b .Lrt_gc_mark_find
+.Lrt_gc_mark_set:
+    ldr x0, [x1, #-16]
+    orr x0, x0, #1
+    str x0, [x1, #-16]
+.Lrt_gc_mark_done:
+    ret
+.section .bss
+.balign 8
+__hamer_gc_objects: .skip 8
+__hamer_gc_bytes: .skip 8
+__hamer_stack_base: .skip 8
+.section .text
+",
+    },
+    Routine {
+        // x0 = length: a new string, a block holding the length followed by that many bytes
+        // (left for the caller to fill). A string value points at the length.
+        name: "__hamer_str_alloc",
+        args: 1,
+        deps: &["__hamer_alloc"],
+        body: "
+    stp x29, x30, [sp, #-16]!
+    str x0, [sp, #-16]!
+    add x0, x0, #8
+    bl __hamer_alloc
+    ldr x1, [sp], #16
+    str x1, [x0]
+    ldp x29, x30, [sp], #16
+    ret
+",
+    },
+    Routine {
+        // x0 = destination, x1 = source, x2 = byte count. Returns the end of the destination.
+        name: "__hamer_copy",
+        args: 3,
+        deps: &[],
+        body: "
+    cbz x2, .Lrt_copy_done
+.Lrt_copy_byte:
+    ldrb w3, [x1], #1
+    strb w3, [x0], #1
+    subs x2, x2, #1
+    b.ne .Lrt_copy_byte
+.Lrt_copy_done:
+    ret
+",
+    },
+    Routine {
+        // x0 = a, x1 = b: a new string holding a then b.
+        name: "__hamer_str_concat",
+        args: 2,
+        deps: &["__hamer_str_alloc", "__hamer_copy"],
+        body: "
+    stp x29, x30, [sp, #-16]!
+    stp x0, x1, [sp, #-16]!
+    ldr x2, [x0]
+    ldr x3, [x1]
+    add x0, x2, x3
+    bl __hamer_str_alloc
+    str x0, [sp, #-16]!
+    ldr x1, [sp, #16]
+    ldr x2, [x1], #8
+    add x0, x0, #8
+    bl __hamer_copy
+    ldr x1, [sp, #24]
+    ldr x2, [x1], #8
+    bl __hamer_copy
+    ldr x0, [sp], #16
+    add sp, sp, #16
+    ldp x29, x30, [sp], #16
+    ret
+",
+    },
+    Routine {
+        // x0 = a, x1 = b: -1, 0 or 1 as a sorts before, with or after b. Bytes compare
+        // unsigned, and a string sorts before any longer string it starts.
+        name: "__hamer_str_cmp",
+        args: 2,
+        deps: &[],
+        body: "
+    ldr x2, [x0], #8
+    ldr x3, [x1], #8
+    cmp x2, x3
+    csel x4, x2, x3, lo
+.Lrt_str_cmp_byte:
+    cbz x4, .Lrt_str_cmp_len
+    ldrb w5, [x0], #1
+    ldrb w6, [x1], #1
+    cmp w5, w6
+    b.ne .Lrt_str_cmp_diff
+    sub x4, x4, #1
+    b .Lrt_str_cmp_byte
+.Lrt_str_cmp_len:
+    cmp x2, x3
+.Lrt_str_cmp_diff:
+    cset x0, hi
+    csinv x0, x0, xzr, hs
+    ret
+",
+    },
+    Routine {
+        // x0 = string, x1 = start, x2 = end: a new string of the bytes in between. Both
+        // ends are clamped to the string, unsigned, so a negative one counts as past the end.
+        name: "__hamer_str_slice",
+        args: 3,
+        deps: &["__hamer_str_alloc", "__hamer_copy"],
+        body: "
+    stp x29, x30, [sp, #-16]!
+    ldr x3, [x0]
+    cmp x2, x3
+    csel x2, x2, x3, ls
+    cmp x1, x2
+    csel x1, x1, x2, ls
+    stp x0, x1, [sp, #-16]!
+    sub x0, x2, x1
+    bl __hamer_str_alloc
+    ldp x1, x2, [sp], #16
+    str x0, [sp, #-16]!
+    add x1, x1, #8
+    add x1, x1, x2
+    ldr x2, [x0]
+    add x0, x0, #8
+    bl __hamer_copy
+    ldr x0, [sp], #16
+    ldp x29, x30, [sp], #16
+    ret
+",
+    },
+    Routine {
+        // x0 = value: a new string of its signed decimal digits.
+        name: "__hamer_str_from_int",
+        args: 1,
+        deps: &["__hamer_str_alloc", "__hamer_copy"],
+        body: "
+    stp x29, x30, [sp, #-16]!
+    sub sp, sp, #32
+    add x1, sp, #32
+    cmp x0, #0
+    cneg x3, x0, lt
+    mov x4, #10
+.Lrt_itos_digit:
+    udiv x5, x3, x4
+    msub x6, x5, x4, x3
+    add x6, x6, #48
+    strb w6, [x1, #-1]!
+    mov x3, x5
+    cbnz x3, .Lrt_itos_digit
+    tbz x0, #63, .Lrt_itos_copy
+    mov w6, #45
+    strb w6, [x1, #-1]!
+.Lrt_itos_copy:
+    add x0, sp, #32
+    sub x0, x0, x1
+    str x1, [sp, #-16]!
+    bl __hamer_str_alloc
+    ldr x1, [sp], #16
+    str x0, [sp, #-16]!
+    ldr x2, [x0]
+    add x0, x0, #8
+    bl __hamer_copy
+    ldr x0, [sp], #16
+    add sp, sp, #32
+    ldp x29, x30, [sp], #16
+    ret
+",
+    },
+    Routine {
+        // x0 = string: the integer it starts with, after an optional sign. Parsing stops
+        // at the first byte that isn't a digit; a string with no digits gives 0.
+        name: "__hamer_str_to_int",
+        args: 1,
+        deps: &[],
+        body: "
+    ldr x1, [x0], #8
+    mov x2, #0
+    mov x3, #0
+    cbz x1, .Lrt_stoi_done
+    ldrb w4, [x0]
+    cmp w4, #45
+    b.ne .Lrt_stoi_plus
+    mov x3, #1
+    b .Lrt_stoi_sign
+.Lrt_stoi_plus:
+    cmp w4, #43
+    b.ne .Lrt_stoi_digit
+.Lrt_stoi_sign:
+    add x0, x0, #1
+    sub x1, x1, #1
+.Lrt_stoi_digit:
+    cbz x1, .Lrt_stoi_done
+    ldrb w4, [x0], #1
+    sub w4, w4, #48
+    cmp w4, #9
+    b.hi .Lrt_stoi_done
+    mov x5, #10
+    madd x2, x2, x5, x4
+    sub x1, x1, #1
+    b .Lrt_stoi_digit
+.Lrt_stoi_done:
+    cmp x3, #0
+    cneg x0, x2, ne
+    ret
+",
+    },
+    Routine {
+        // x0 = string, x1 = byte to append (0 for none), x2 = fd.
+        name: "__hamer_str_print",
+        args: 3,
+        deps: &[],
+        body: "
+    mov x3, x0
+    mov x4, x1
+    mov x5, x2
+    mov x0, x5
+    add x1, x3, #8
+    ldr x2, [x3]
+    mov x8, #64
+    svc #0
+    cbz x4, .Lrt_str_print_done
+    strb w4, [sp, #-16]!
+    mov x0, x5
+    mov x1, sp
+    mov x2, #1
+    mov x8, #64
+    svc #0
+    add sp, sp, #16
+.Lrt_str_print_done:
+    ret
+",
+    },
+    Routine {
+        // x0 = bool, x1 = byte to append (0 for none), x2 = fd. Prints `true` or `false`.
+        name: "__hamer_print_bool",
+        args: 3,
+        deps: &["__hamer_str_print"],
+        body: "
+    adr x3, .Lrt_true
+    adr x4, .Lrt_false
+    cmp x0, #0
+    csel x0, x3, x4, ne
+    b __hamer_str_print
+.section .data
+.balign 8
+.Lrt_true: .quad 4
+    .ascii \"true\"
+.balign 8
+.Lrt_false: .quad 5
+    .ascii \"false\"
+.section .text
+",
+    },
+    Routine {
+        // Reached when mmap fails: reports on stderr and exits with status 12 (ENOMEM).
+        name: "__hamer_oom",
+        args: 0,
+        deps: &[],
+        body: "
+    mov x0, #2
+    adr x1, .Lrt_oom_msg
+    mov x2, #35
+    mov x8, #64
+    svc #0
+    mov x0, #12
+    mov x8, #93
+    svc #0
+.section .data
+.Lrt_oom_msg: .ascii \"H@mer: out of memory (mmap failed)\\n\"
+.section .text
+",
+    },
+    Routine {
+        // Reached with `cbz divisor, __hamer_div_zero`: AArch64 division by zero quietly
+        // gives 0, so integer `/` and `%` trap here instead. Exits with status 8, like SIGFPE.
+        name: "__hamer_div_zero",
+        args: 0,
+        deps: &[],
+        body: "
+    mov x0, #2
+    adr x1, .Lrt_div_zero_msg
+    mov x2, #24
+    mov x8, #64
+    svc #0
+    mov x0, #8
+    mov x8, #93
+    svc #0
+.section .data
+.Lrt_div_zero_msg: .ascii \"H@mer: division by zero\\n\"
+.section .text
+",
+    },
+    Routine {
+        // Reached with `b.hs __hamer_bounds` under --bounds-check, with the index in x5 and
+        // the array length in x6. Reports both on stderr and exits with status 11, like SIGSEGV.
+        name: "__hamer_bounds",
+        args: 0,
+        deps: &["__hamer_print_i64"],
+        body: "
+    stp x5, x6, [sp, #-16]!
+    mov x0, #2
+    adr x1, .Lrt_bounds_msg
+    mov x2, #13
+    mov x8, #64
+    svc #0
+    ldr x0, [sp]
+    mov x1, #0
+    mov x2, #2
+    bl __hamer_print_i64
+    mov x0, #2
+    adr x1, .Lrt_bounds_len_msg
+    mov x2, #26
+    mov x8, #64
+    svc #0
+    ldr x0, [sp, #8]
+    mov x1, #10
+    mov x2, #2
+    bl __hamer_print_i64
+    mov x0, #11
+    mov x8, #93
+    svc #0
+.section .data
+.Lrt_bounds_msg: .ascii \"H@mer: index \"
+.Lrt_bounds_len_msg: .ascii \" out of bounds for length \"
+.section .text
+",
+    },
+    Routine {
+        name: "__hamer_roll",
+        args: 1,
+        deps: &[],
+        body: "
+    ldr x1, [x0, #8]
+    cbnz x1, .Lrt_roll_seeded
+    mrs x1, cntvct_el0
+.Lrt_roll_seeded:
+    ldr x2, =0x9E3779B97F4A7C15
+    mul x1, x1, x2
+    eor x1, x1, x1, lsr #33
+    str x1, [x0, #8]
+    and x1, x1, #0x7FFFFFFF
+    mov x2, #100
+    udiv x3, x1, x2
+    msub x0, x3, x2, x1
+    ret
+",
+    },
+];
+
+/// Under --gc these replace the routines of the same name.
+const GC_ROUTINES: &[Routine] = &[
+    Routine {
+        // Strings are collected objects with no references in them.
+        name: "__hamer_str_alloc",
+        args: 1,
+        deps: &["__hamer_gc_alloc"],
+        body: "
+    stp x29, x30, [sp, #-16]!
+    str x0, [sp, #-16]!
+    add x0, x0, #8
+    adr x1, .Lrt_str_type
+    bl __hamer_gc_alloc
+    ldr x1, [sp], #16
+    str x1, [x0]
+    ldp x29, x30, [sp], #16
+    ret
+.section .data
+.balign 8
+.Lrt_str_type: .quad 0, 0
+.section .text
+",
+    },
+];
+
+/// Number of argument registers a runtime routine reads, if `name` is one.
+pub fn arity(name: &str) -> Option<usize> {
+    ROUTINES.iter().find(|r| r.name == name).map(|r| r.args)
+}
+
+/// Tracks which routines the program calls so only those get emitted.
+#[derive(Default)]
+pub struct Runtime {
+    used: Vec<&'static str>,
+    /// Emit the collector's versions of the routines in `GC_ROUTINES`.
+    pub gc: bool,
+}
+
+impl Runtime {
+    pub fn call(&mut self, name: &'static str) -> String {
+        self.require(name);
+        format!("    bl {}\n", name)
+    }
+
+    pub fn require(&mut self, name: &'static str) {
+        if self.used.contains(&name) { return; }
+        self.used.push(name);
+        if let Some(r) = self.routine(name) {
+            for dep in r.deps { self.require(dep); }
+        }
+    }
+
+    fn routine(&self, name: &str) -> Option<&'static Routine> {
+        let gc = if self.gc { GC_ROUTINES } else { &[] };
+        gc.iter().chain(ROUTINES).find(|r| r.name == name)
+    }
+
+    pub fn emit(&self) -> String {
+        let mut out = String::new();
+        for r in ROUTINES.iter().filter(|r| self.used.contains(&r.name)) {
+            let r = self.routine(r.name).unwrap_or(r);
+            out.push_str(&format!("\n{}:{}", r.name, r.body));
+        }
+        out
+    }
+}
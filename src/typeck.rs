@@ -0,0 +1,616 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::layout::{self, ClassLayout, FieldType};
+use crate::lexer::{Span, Token};
+use crate::parser::{self, Expr, FormatSpec, Path, Stmt};
+
+/// Static type of a value. Fields typed `ptr` or left untyped have no static type
+/// and are accepted anywhere a number or a reference is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int { bits: u8, signed: bool },
+    Float,
+    Str,
+    Bool,
+    /// A reference to an object of the named class.
+    Obj(String),
+}
+
+impl Type {
+    const I64: Type = Type::Int { bits: 64, signed: true };
+
+    fn of_field(ty: FieldType) -> Option<Type> {
+        let (bits, signed) = match ty {
+            FieldType::Any | FieldType::Ptr => return None,
+            FieldType::F64 => return Some(Type::Float),
+            FieldType::Str => return Some(Type::Str),
+            FieldType::Bool => return Some(Type::Bool),
+            t => ((t.size() * 8) as u8, t.signed()),
+        };
+        Some(Type::Int { bits, signed })
+    }
+
+    /// Whether a value of type `v` can be assigned to this type. Integers of any width
+    /// mix, narrowing on store, and a float target takes integers too.
+    fn accepts(&self, v: &Type) -> bool {
+        match (self, v) {
+            (Type::Int { .. }, Type::Int { .. }) | (Type::Float, Type::Int { .. } | Type::Float) => true,
+            (Type::Str, Type::Str) | (Type::Bool, Type::Bool) => true,
+            (Type::Obj(a), Type::Obj(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    fn of_literal(v: f64) -> Type {
+        if v.fract() == 0.0 { Type::I64 } else { Type::Float }
+    }
+
+    /// Whether values of the two types can be compared: numbers with numbers, strings
+    /// with strings and references to the same class with each other.
+    fn compares(&self, v: &Type) -> bool {
+        let numeric = |t: &Type| matches!(t, Type::Int { .. } | Type::Float);
+        (numeric(self) && numeric(v)) || self == v
+    }
+
+    /// Whether the literal `v` can be stored in a value of this type.
+    fn holds(&self, v: f64) -> bool {
+        match self {
+            Type::Int { bits, signed } => {
+                let (lo, hi) = if *signed {
+                    (-(2f64.powi(*bits as i32 - 1)), 2f64.powi(*bits as i32 - 1) - 1.0)
+                } else {
+                    (0.0, 2f64.powi(*bits as i32) - 1.0)
+                };
+                v.fract() == 0.0 && (lo..=hi).contains(&v)
+            }
+            Type::Float => true,
+            // Only the null reference is a valid numeric value for an object.
+            Type::Obj(_) => v == 0.0,
+            Type::Str | Type::Bool => false,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int { bits, signed } => write!(f, "{}{}", if *signed { "i" } else { "u" }, bits),
+            Type::Float => write!(f, "float"),
+            Type::Str => write!(f, "string"),
+            Type::Bool => write!(f, "bool"),
+            Type::Obj(c) => write!(f, "`{}` reference", c),
+        }
+    }
+}
+
+/// The type environment, shaped like the generator's: class layouts by name and the
+/// class of every local that holds an object.
+struct Checker {
+    class_map: HashMap<String, ClassLayout>,
+    obj_types: HashMap<String, String>,
+    /// Types of the locals that hold numbers.
+    locals: HashMap<String, Type>,
+    /// Locals holding an object that `free` must leave alone, with what releases it:
+    /// the end of the block for a `stack` object, the `done` of the arena it came from.
+    released: HashMap<String, &'static str>,
+    /// How many `arena` blocks the current statement is in.
+    arenas: usize,
+    /// Set while checking a `print`, the one place an interpolated string may appear.
+    in_print: bool,
+    /// Compiling for `--gc`, whose collector only looks for references in whole words.
+    gc: bool,
+    /// Where the statement being checked starts; errors without a closer place point here.
+    span: Span,
+    errors: Vec<String>,
+}
+
+/// Checks that every operation is applied to values of a type it accepts.
+/// Returns one message per error; an empty list means the program is well typed.
+pub fn check(ast: &[Stmt], gc: bool) -> Vec<String> {
+    let mut c = Checker {
+        class_map: HashMap::new(),
+        obj_types: HashMap::new(),
+        locals: HashMap::new(),
+        released: HashMap::new(),
+        arenas: 0,
+        in_print: false,
+        gc,
+        span: Span::default(),
+        errors: Vec::new(),
+    };
+    for s in ast { c.stmt(s); }
+    c.errors
+}
+
+impl Checker {
+    fn error(&mut self, msg: String) {
+        self.error_at(self.span, msg);
+    }
+
+    fn error_at(&mut self, span: Span, msg: String) {
+        self.errors.push(format!("{}: {}", span, msg));
+    }
+
+    /// Checks a nested block; the locals it declares are gone after its `done`.
+    fn block(&mut self, stmts: &[Stmt]) {
+        let (obj_types, locals, released, span) = (self.obj_types.clone(), self.locals.clone(), self.released.clone(), self.span);
+        for s in stmts { self.stmt(s); }
+        (self.obj_types, self.locals, self.released, self.span) = (obj_types, locals, released, span);
+    }
+
+    /// Records what releases the object a local now holds: the same as the local it
+    /// was copied from, or nothing when it holds anything else.
+    fn track_release(&mut self, name: &str, value: &Expr) {
+        match value {
+            Expr::Path(p) if p.is_local() && let Some(by) = self.released.get(&p[0]).copied() => {
+                self.released.insert(name.to_string(), by);
+            }
+            _ => { self.released.remove(name); }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        if let Some(span) = stmt.span() { self.span = span; }
+        match stmt {
+            Stmt::ClassDef { name, fields, packed, .. } => {
+                for f in fields {
+                    if let Some(t) = &f.ty
+                        && FieldType::parse(t).is_none()
+                    {
+                        self.error_at(f.span, format!("unknown type `{}` for field `{}.{}`", t, name, f.name));
+                    }
+                }
+                let layout = ClassLayout::new(fields, *packed);
+                // Only an `@offset` can move a field onto bytes another one already uses.
+                for (i, (f, decl)) in layout.fields.iter().zip(fields).enumerate() {
+                    if let Some(g) = layout.fields[..i].iter().find(|g| f.offset < g.end() && g.offset < f.end()) {
+                        self.error_at(decl.span, format!(
+                            "field `{}.{}` at bytes {}..{} overlaps `{}` at {}..{}",
+                            name, f.name, f.offset, f.end(), g.name, g.offset, g.end()
+                        ));
+                    }
+                }
+                if self.gc {
+                    for f in layout.fields.iter().filter(|f| f.ty.may_ref() && f.offset % 8 != 0) {
+                        let span = fields.iter().find(|d| d.name == f.name).map_or(self.span, |d| d.span);
+                        self.error_at(span, format!(
+                            "field `{}.{}` may hold a reference but sits at offset {}; with `--gc` such fields must be 8-byte aligned",
+                            name, f.name, f.offset
+                        ));
+                    }
+                }
+                self.class_map.insert(name.clone(), layout);
+            }
+            Stmt::LocalAssign { name, ty, value, .. } => {
+                let v = self.expr(value);
+                self.obj_types.remove(name);
+                let declared = match ty.as_deref().map(|t| (t, FieldType::parse(t))) {
+                    None => v.clone().unwrap_or(Type::I64),
+                    Some((_, Some(ft))) if ft != FieldType::Ptr && ft != FieldType::Any => {
+                        Type::of_field(ft).unwrap_or(Type::I64)
+                    }
+                    Some((t, _)) => {
+                        self.error(format!("unknown type `{}` for local `{}`", t, name));
+                        Type::I64
+                    }
+                };
+                match (value, v) {
+                    (Expr::Number(n), _) if !declared.holds(*n) && matches!(declared, Type::Int { .. }) => {
+                        self.error(format!("{} does not fit in `{}` of type {}", n, name, declared));
+                    }
+                    (Expr::Number(n), _) if !declared.holds(*n) => {
+                        self.error(format!("cannot assign {} to `{}` of type {}", n, name, declared));
+                    }
+                    (Expr::Number(_), _) | (_, None) => {}
+                    (_, Some(v)) if !declared.accepts(&v) => {
+                        self.error(format!("cannot assign {} to `{}` of type {}", v, name, declared));
+                    }
+                    _ => {}
+                }
+                if let Type::Obj(class) = &declared {
+                    self.locals.remove(name);
+                    self.obj_types.insert(name.clone(), class.clone());
+                } else {
+                    self.locals.insert(name.clone(), declared);
+                }
+                self.track_release(name, value);
+            }
+            Stmt::GlobalDef { name, ty, value, .. } => {
+                let v = value.as_ref().and_then(|v| self.expr(v));
+                let declared = match ty.as_deref().map(|t| (t, FieldType::parse(t).and_then(Type::of_field))) {
+                    None => v.clone().unwrap_or(Type::I64),
+                    Some((_, Some(t))) => t,
+                    Some(("ptr", _)) => {
+                        self.error(format!("global `{}` can't hold a pointer; globals hold numbers and bools", name));
+                        Type::I64
+                    }
+                    Some((t, None)) => {
+                        self.error(format!("unknown type `{}` for global `{}`", t, name));
+                        Type::I64
+                    }
+                };
+                // A global is laid out in `.data` before the program runs.
+                match (value, v) {
+                    _ if matches!(declared, Type::Str | Type::Obj(_)) => {
+                        self.error(format!("global `{}` can't hold a {}; globals hold numbers and bools", name, declared));
+                    }
+                    (None, _) => {}
+                    (Some(Expr::Number(n)), _) if !declared.holds(*n) && matches!(declared, Type::Int { .. }) => {
+                        self.error(format!("{} does not fit in `{}` of type {}", n, name, declared));
+                    }
+                    (Some(Expr::Number(n)), _) if !declared.holds(*n) => {
+                        self.error(format!("cannot assign {} to `{}` of type {}", n, name, declared));
+                    }
+                    (Some(Expr::Number(_) | Expr::Bool(_)), Some(v)) if declared.accepts(&v) => {}
+                    (Some(Expr::Number(_) | Expr::Bool(_)), Some(v)) => {
+                        self.error(format!("cannot assign {} to `{}` of type {}", v, name, declared));
+                    }
+                    _ => self.error(format!("the initial value of global `{}` must be a constant", name)),
+                }
+                self.obj_types.remove(name);
+                self.locals.insert(name.clone(), declared);
+            }
+            Stmt::HeapAlloc { var_name, class_name, .. } | Stmt::StackAlloc { var_name, class_name, .. } => {
+                if let Some((elem, len)) = layout::array_type(class_name)
+                    && !self.class_map.contains_key(class_name)
+                {
+                    let ty = FieldType::parse(elem).unwrap_or_else(|| {
+                        self.error(format!("unknown element type `{}` for array `{}`", elem, var_name));
+                        FieldType::Any
+                    });
+                    self.class_map.insert(class_name.clone(), ClassLayout::array(ty, len));
+                }
+                self.locals.remove(var_name);
+                self.obj_types.insert(var_name.clone(), class_name.clone());
+                if matches!(stmt, Stmt::StackAlloc { .. }) {
+                    self.released.insert(var_name.clone(), "the end of its block");
+                } else if self.arenas > 0 {
+                    self.released.insert(var_name.clone(), "the `done` of its arena");
+                } else {
+                    self.released.remove(var_name);
+                }
+            }
+            Stmt::FieldAssign { path, value: Expr::Number(value) } => match self.path_type(path) {
+                Some(t) if !t.holds(*value) => {
+                    self.error(format!("cannot assign {} to `{}` of type {}", value, path.join("."), t));
+                }
+                // An untyped slot can't tell a float's bits from an integer when it's read back.
+                None if value.fract() != 0.0 && path.len() > 1 => {
+                    self.error(format!("cannot store {} in untyped field `{}`; declare it `f64`", value, path.join(".")));
+                }
+                _ => {}
+            },
+            Stmt::FieldAssign { path, value } => {
+                if path.is_local() { self.track_release(&path[0], value); }
+                let (target, value) = (self.path_type(path), self.expr(value));
+                match (target, value) {
+                    (Some(t), Some(v)) if !t.accepts(&v) => {
+                        self.error(format!("cannot assign {} to `{}` of type {}", v, path.join("."), t));
+                    }
+                    (None, Some(Type::Float)) if path.len() > 1 => {
+                        self.error(format!("cannot store a float in untyped field `{}`; declare it `f64`", path.join(".")));
+                    }
+                    (None, Some(t @ (Type::Str | Type::Bool))) if path.len() > 1 => {
+                        let ty = if t == Type::Str { "str" } else { "bool" };
+                        self.error(format!("cannot store a {} in untyped field `{}`; declare it `{}`", t, path.join("."), ty));
+                    }
+                    (None, Some(Type::Str)) => {
+                        self.error(format!("cannot store a string in `{}`", path.join(".")));
+                    }
+                    _ => {}
+                }
+            }
+            Stmt::FieldMath { path, op, rhs } => {
+                let r = self.expr(rhs);
+                match self.path_type(path) {
+                    Some(Type::Obj(c)) => {
+                        self.error(format!("cannot use `{}` reference `{}` as a counter", c, path.join(".")));
+                    }
+                    Some(t @ Type::Int { .. }) if r == Some(Type::Float) => {
+                        let what = match rhs { Expr::Number(v) => v.to_string(), _ => "a float".into() };
+                        self.error(format!("`{}` is {}, so {} would be truncated", path.join("."), t, what));
+                    }
+                    Some(_) if matches!(op, Token::Slash | Token::Percent) && matches!(rhs, Expr::Number(n) if *n == 0.0) => {
+                        self.error(format!("division of `{}` by zero", path.join(".")));
+                    }
+                    Some(t) => if let Some(r) = r { self.arith(op, &t, &r) },
+                    // Like a store, the untyped slot would keep the truncated integer.
+                    None if r == Some(Type::Float) && path.len() > 1 => {
+                        let what = match rhs { Expr::Number(v) => v.to_string(), _ => "a float".into() };
+                        self.error_at(path.span, format!("`{}` is untyped, so {} would be truncated; declare it `f64`", path.join("."), what));
+                    }
+                    None => {}
+                }
+            }
+            Stmt::IfStmt { cond, body, .. } | Stmt::WhileStmt { cond, body, .. } => {
+                match self.expr(cond) {
+                    Some(Type::Bool) | None => {}
+                    Some(t) => self.error(format!("a condition must be a bool, found {}", t)),
+                }
+                self.block(body);
+            }
+            Stmt::ForStmt { var, start, end, step, body, .. } => {
+                for (what, e) in [("start", Some(start)), ("end", Some(end)), ("step", step.as_ref())] {
+                    let Some(e) = e else { continue };
+                    match self.expr(e) {
+                        Some(Type::Int { .. }) | None => {}
+                        Some(t) => self.error(format!("the {} of a `for` range must be an integer, found {}", what, t)),
+                    }
+                }
+                if let Some(Expr::Number(n)) = step
+                    && *n == 0.0
+                {
+                    self.error(format!("the step of `for {}` can't be 0", var));
+                }
+                let (obj_types, locals) = (self.obj_types.clone(), self.locals.clone());
+                self.obj_types.remove(var);
+                self.locals.insert(var.clone(), Type::I64);
+                self.block(body);
+                (self.obj_types, self.locals) = (obj_types, locals);
+            }
+            Stmt::Match { value, arms, .. } => {
+                let t = self.expr(value);
+                if let Some(t) = &t
+                    && !matches!(t, Type::Int { .. })
+                {
+                    self.error(format!("`match` needs an integer, found {}", t));
+                }
+                let mut seen: Vec<(i64, Span)> = Vec::new();
+                for arm in arms {
+                    for v in &arm.values {
+                        let Some(n) = v.const_int() else {
+                            self.error_at(arm.span, "a `match` arm needs an integer constant".to_string());
+                            continue;
+                        };
+                        match seen.iter().find(|(m, _)| *m == n) {
+                            Some((_, first)) if *first == arm.span => {
+                                self.error_at(arm.span, format!("{} appears twice in this arm", n));
+                            }
+                            Some((_, first)) => {
+                                self.error_at(arm.span, format!("{} is already matched by the arm at {}", n, first));
+                            }
+                            None => {}
+                        }
+                        seen.push((n, arm.span));
+                        if let Some(t @ Type::Int { .. }) = &t
+                            && !t.holds(n as f64)
+                        {
+                            self.error_at(arm.span, format!("arm {} can never match a {}", n, t));
+                        }
+                    }
+                    self.block(&arm.body);
+                }
+            }
+            Stmt::Break { .. } | Stmt::Continue { .. } => {}
+            Stmt::Print { items, .. } => {
+                self.in_print = true;
+                for e in items { self.expr(e); }
+                self.in_print = false;
+            }
+            Stmt::Exit { status, .. } => match (status, self.expr(status)) {
+                // The kernel keeps only the low 8 bits of the status.
+                (Expr::Number(n), _) if !(0.0..=255.0).contains(n) => {
+                    self.error(format!("exit status {} is out of range 0..=255", n));
+                }
+                (_, Some(Type::Int { .. }) | None) => {}
+                (_, Some(t)) => self.error(format!("the status of `exit` must be an integer, found {}", t)),
+            },
+            Stmt::Free { value: e, .. } => match self.expr(e) {
+                Some(Type::Obj(_)) if let Expr::Path(p) = e && p.is_local() && let Some(by) = self.released.get(&p[0]) => {
+                    self.error_at(p.span, format!("cannot `free` `{}`; it is released at {}", p[0], by));
+                }
+                Some(Type::Obj(_)) | None => {}
+                Some(t) => self.error(format!("`free` needs an object reference, found {}", t)),
+            },
+            Stmt::Arena(body) => {
+                self.arenas += 1;
+                self.block(body);
+                self.arenas -= 1;
+            }
+            Stmt::ProbIf { body, .. } => self.block(body),
+            Stmt::MergeBlock(body) => for s in body { self.stmt(s) },
+            // Their uses were folded into numbers by `consts::fold`.
+            Stmt::Const { .. } | Stmt::EnumDef { .. } => {}
+            Stmt::AsmBlock(_) | Stmt::IntelBlock(_) | Stmt::PythonBlock(_) => {}
+        }
+    }
+
+    /// Type of a local, field or array element, or `None` when it has no static type.
+    fn path_type(&mut self, path: &Path) -> Option<Type> {
+        let base = &path[0];
+        let name = path.join(".");
+        let Some(class) = self.obj_types.get(base).cloned() else {
+            if let Some(t) = self.locals.get(base) {
+                if path.len() > 1 {
+                    self.error_at(path.span, format!("`{}` is {}, it has no field `{}`", base, t, path[1]));
+                } else if path.index.is_some() {
+                    self.error_at(path.span, format!("`{}` is {}, it can't be indexed", base, t));
+                }
+            }
+            return self.locals.get(base).cloned().filter(|_| path.is_local());
+        };
+        if path.is_local() { return Some(Type::Obj(class)); }
+        if path.len() > 2 { return None; }
+        let array = layout::array_type(&class).is_some();
+        let field = match path.get(1) {
+            Some(f) if array => {
+                self.error_at(path.span, format!("`{}` is an array, it has no field `{}`", base, f));
+                return None;
+            }
+            None if !array => {
+                self.error_at(path.span, format!("`{}` is a `{}` reference, it can't be indexed", base, class));
+                return None;
+            }
+            Some(f) => self.class_map.get(&class)?.field(f)?,
+            None => self.class_map.get(&class)?.fields.first()?,
+        };
+        let (ty, len) = (field.ty, field.len);
+        match (&path.index, len) {
+            (Some(index), Some(len)) => self.index(&name, index, len, path.span),
+            (None, Some(_)) => {
+                self.error_at(path.span, format!("`{}` is an array; index it like `{}[i]`", name, name));
+                return None;
+            }
+            (Some(_), None) => {
+                self.error_at(path.span, format!("`{}` is not an array, it can't be indexed", name));
+                return None;
+            }
+            (None, None) => {}
+        }
+        Type::of_field(ty)
+    }
+
+    /// Indices are integers, and a constant one has to be in range.
+    fn index(&mut self, array: &str, index: &Expr, len: usize, span: Span) {
+        match (index, self.expr(index)) {
+            (Expr::Number(n), _) if *n < 0.0 || *n >= len as f64 => {
+                self.error_at(span, format!("index {} is out of bounds for `{}` of length {}", n, array, len));
+            }
+            (_, Some(Type::Int { .. }) | None) => {}
+            (_, Some(t)) => self.error_at(span, format!("index of `{}` must be an integer, found {}", array, t)),
+        }
+    }
+
+    fn expr(&mut self, e: &Expr) -> Option<Type> {
+        match e {
+            Expr::Number(n) => Some(Type::of_literal(*n)),
+            Expr::Str(_) => Some(Type::Str),
+            Expr::Interp(parts) => {
+                if !self.in_print {
+                    self.error("interpolated strings can only be printed; build the string with `+` and `str()`".to_string());
+                }
+                for p in parts { self.expr(p); }
+                Some(Type::Str)
+            }
+            Expr::Path(p) => self.path_type(p),
+            Expr::Format { value, spec } => {
+                let t = self.expr(value);
+                if let Some(t @ (Type::Str | Type::Bool)) = &t
+                    && *spec != FormatSpec::default()
+                {
+                    self.error(format!("a {} can't take a format spec", t));
+                }
+                t
+            }
+            Expr::Call { name, args, span } => self.call(name, args, *span),
+            Expr::Slice { value, start, end } => {
+                match self.expr(value) {
+                    Some(Type::Str) | None => {}
+                    Some(t) => self.error(format!("cannot slice {}", t)),
+                }
+                for bound in [start, end].into_iter().flatten() {
+                    match self.expr(bound) {
+                        Some(Type::Int { .. }) | None => {}
+                        Some(t) => self.error(format!("slice bounds must be integers, found {}", t)),
+                    }
+                }
+                Some(Type::Str)
+            }
+            Expr::Neg(inner) => {
+                let t = self.expr(inner)?;
+                if matches!(t, Type::Str | Type::Bool | Type::Obj(_)) {
+                    self.error(format!("cannot negate {}", t));
+                    return None;
+                }
+                Some(t)
+            }
+            Expr::Bool(_) => Some(Type::Bool),
+            Expr::Binary { op, lhs, rhs } if parser::is_compare(op) => {
+                self.compare(lhs, rhs);
+                Some(Type::Bool)
+            }
+            Expr::Binary { op, lhs, rhs } => {
+                if matches!(op, Token::Slash | Token::Percent) && matches!(**rhs, Expr::Number(n) if n == 0.0) {
+                    self.error("division by zero".to_string());
+                }
+                let (l, r) = (self.expr(lhs), self.expr(rhs));
+                let (l, r) = (l?, r?);
+                self.arith(op, &l, &r);
+                match (l, r) {
+                    (Type::Str, Type::Str) => Some(Type::Str),
+                    (Type::Float, _) | (_, Type::Float) => Some(Type::Float),
+                    (Type::Int { signed: a, .. }, Type::Int { signed: b, .. }) => {
+                        Some(Type::Int { bits: 64, signed: a || b })
+                    }
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Numbers compare with numbers and everything else with its own type. A reference
+    /// only compares with the literal 0, and a literal has to fit the other side.
+    fn compare(&mut self, lhs: &Expr, rhs: &Expr) {
+        let (l, r) = (self.expr(lhs), self.expr(rhs));
+        let (Some(l), Some(r)) = (l, r) else { return };
+        let bad = match rhs {
+            Expr::Number(n) => !l.holds(*n),
+            _ => !l.compares(&r),
+        };
+        if !bad { return; }
+        let what = match rhs { Expr::Number(n) => n.to_string(), _ => r.to_string() };
+        match lhs {
+            Expr::Path(p) => self.error_at(p.span, format!("cannot compare `{}` of type {} with {}", p.join("."), l, what)),
+            _ => self.error(format!("cannot compare {} with {}", l, what)),
+        }
+    }
+
+    /// The builtins: `len` of a string or array, `str` of an integer and `int` of a string.
+    fn call(&mut self, name: &str, args: &[Expr], span: Span) -> Option<Type> {
+        let types: Vec<_> = args.iter().map(|a| self.expr(a)).collect();
+        if types.len() != 1 {
+            self.error_at(span, format!("`{}` takes 1 argument, found {}", name, types.len()));
+            return None;
+        }
+        let (arg, ret) = match name {
+            "len" => (Type::Str, Type::I64),
+            "str" => (Type::I64, Type::Str),
+            "int" => (Type::Str, Type::I64),
+            _ => return None,
+        };
+        match &types[0] {
+            Some(Type::Obj(c)) if name == "len" && layout::array_type(c).is_some() => {}
+            Some(t) if !arg.accepts(t) => {
+                self.error_at(span, format!("`{}` needs {}, found {}", name, if arg == Type::Str { "a string" } else { "an integer" }, t));
+            }
+            _ => {}
+        }
+        Some(ret)
+    }
+
+    /// Integers of any width and floats combine; an integer mixed with a float is converted.
+    /// `%`, bitwise operators and shifts only take integers.
+    fn arith(&mut self, op: &Token, l: &Type, r: &Type) {
+        let bits_only = matches!(op, Token::Percent | Token::Amp | Token::Pipe | Token::Caret | Token::Shl | Token::Shr);
+        let numeric = |t: &Type| matches!(t, Type::Int { .. }) || (!bits_only && *t == Type::Float);
+        if *op == Token::Plus && *l == Type::Str && *r == Type::Str { return; }
+        if bits_only && (!numeric(l) || !numeric(r)) {
+            self.error(format!("cannot apply `{}` to {} and {}", op_symbol(op), l, r));
+        } else if !numeric(l) || !numeric(r) {
+            let (verb, prep) = match op {
+                Token::Minus => ("subtract", "from"),
+                Token::Star => ("multiply", "by"),
+                Token::Slash => ("divide", "by"),
+                _ => ("add", "to"),
+            };
+            // `a - b` reads "subtract b from a", the others "add a to b".
+            let (first, second) = if *op == Token::Minus { (r, l) } else { (l, r) };
+            self.error(format!("cannot {} {} {} {}", verb, first, prep, second));
+        }
+    }
+}
+
+fn op_symbol(op: &Token) -> &'static str {
+    match op {
+        Token::Percent => "%",
+        Token::Amp => "&",
+        Token::Pipe => "|",
+        Token::Caret => "^",
+        Token::Shl => "<<",
+        Token::Shr => ">>",
+        Token::Star => "*",
+        Token::Slash => "/",
+        Token::Minus => "-",
+        _ => "+",
+    }
+}
@@ -0,0 +1,145 @@
+//! Compiles every program in `tests/samples` and checks what it prints against
+//! `name.out` and its exit status against `name.status` (0 when there is none).
+//! Flags for the compiler go in `name.flags`. Programs in `tests/errors` must fail
+//! to compile, printing the lines in `name.err`.
+//!
+//! The programs only run where AArch64 Linux binaries can: natively, or through
+//! `qemu-aarch64` with the `aarch64-linux-gnu` binutils. Elsewhere the assembly is
+//! still checked with `llvm-mc` when it is installed.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// How far a compiled sample can be taken on this machine.
+enum Toolchain {
+    /// Assembler, linker and the emulator that runs the result, if it can't run as is.
+    Run { as_cmd: &'static str, ld_cmd: &'static str, runner: Option<&'static str> },
+    Assemble,
+    CompileOnly,
+}
+
+fn installed(cmd: &str) -> bool {
+    Command::new(cmd).arg("--version").output().is_ok_and(|o| o.status.success())
+}
+
+fn toolchain() -> Toolchain {
+    if cfg!(all(target_arch = "aarch64", target_os = "linux")) && installed("as") && installed("ld") {
+        return Toolchain::Run { as_cmd: "as", ld_cmd: "ld", runner: None };
+    }
+    if installed("qemu-aarch64") && installed("aarch64-linux-gnu-as") && installed("aarch64-linux-gnu-ld") {
+        return Toolchain::Run { as_cmd: "aarch64-linux-gnu-as", ld_cmd: "aarch64-linux-gnu-ld", runner: Some("qemu-aarch64") };
+    }
+    if installed("llvm-mc") { Toolchain::Assemble } else { Toolchain::CompileOnly }
+}
+
+/// The `.hmr` files in `tests/<dir>`, in name order.
+fn programs(dir: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join(dir);
+    let mut files: Vec<PathBuf> = fs::read_dir(dir).expect("missing test directory")
+        .map(|e| e.expect("unreadable test directory").path())
+        .filter(|p| p.extension().is_some_and(|e| e == "hmr"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// The file next to `src` with extension `ext`, if there is one.
+fn companion(src: &Path, ext: &str) -> Option<String> {
+    fs::read_to_string(src.with_extension(ext)).ok()
+}
+
+/// Compiles `src` in a scratch directory of its own, since the compiler writes `out.s`
+/// where it runs. Messages name the file without its directory.
+fn compile(src: &Path) -> (Output, PathBuf) {
+    let name = src.file_name().expect("program without a name");
+    let dir = std::env::temp_dir().join(format!("hamer-{}-{}", std::process::id(), name.to_string_lossy()));
+    fs::create_dir_all(&dir).expect("cannot create a scratch directory");
+    fs::copy(src, dir.join(name)).expect("cannot copy the program");
+    let flags = companion(src, "flags").unwrap_or_default();
+    let out = Command::new(env!("CARGO_BIN_EXE_hamer"))
+        .args(flags.split_whitespace())
+        .arg(name)
+        .current_dir(&dir)
+        .output()
+        .expect("cannot start the compiler");
+    (out, dir)
+}
+
+fn run(cmd: &mut Command, what: &str) -> Output {
+    cmd.output().unwrap_or_else(|e| panic!("cannot start {}: {}", what, e))
+}
+
+/// Builds and runs one sample, returning a description of what went wrong.
+fn check_sample(src: &Path, tools: &Toolchain) -> Result<(), String> {
+    let (out, dir) = compile(src);
+    if !out.status.success() {
+        return Err(format!("does not compile:\n{}", String::from_utf8_lossy(&out.stderr)));
+    }
+    match tools {
+        Toolchain::CompileOnly => {}
+        Toolchain::Assemble => {
+            let asm = run(Command::new("llvm-mc").args(["-triple=aarch64-linux-gnu", "-filetype=obj", "out.s", "-o", "out.o"]).current_dir(&dir), "llvm-mc");
+            if !asm.status.success() {
+                return Err(format!("does not assemble:\n{}", String::from_utf8_lossy(&asm.stderr)));
+            }
+        }
+        Toolchain::Run { as_cmd, ld_cmd, runner } => {
+            let asm = run(Command::new(as_cmd).args(["out.s", "-o", "out.o"]).current_dir(&dir), as_cmd);
+            if !asm.status.success() {
+                return Err(format!("does not assemble:\n{}", String::from_utf8_lossy(&asm.stderr)));
+            }
+            let link = run(Command::new(ld_cmd).args(["out.o", "-o", "prog"]).current_dir(&dir), ld_cmd);
+            if !link.status.success() {
+                return Err(format!("does not link:\n{}", String::from_utf8_lossy(&link.stderr)));
+            }
+            let prog = dir.join("prog");
+            let mut cmd = match runner {
+                Some(r) => { let mut c = Command::new(r); c.arg(&prog); c }
+                None => Command::new(&prog),
+            };
+            let got = run(cmd.current_dir(&dir), "the program");
+            let want_out = companion(src, "out").unwrap_or_default();
+            let want_status: i32 = companion(src, "status").map_or(0, |s| s.trim().parse().expect("bad .status file"));
+            let stdout = String::from_utf8_lossy(&got.stdout);
+            if stdout != want_out {
+                return Err(format!("printed\n{}\nbut should print\n{}", stdout, want_out));
+            }
+            if got.status.code() != Some(want_status) {
+                return Err(format!("exited with {:?}, not {}", got.status.code(), want_status));
+            }
+        }
+    }
+    fs::remove_dir_all(&dir).ok();
+    Ok(())
+}
+
+#[test]
+fn samples() {
+    let tools = toolchain();
+    let failures: Vec<String> = programs("samples").iter()
+        .filter_map(|src| check_sample(src, &tools).err().map(|e| format!("{}: {}", src.display(), e)))
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
+
+#[test]
+fn errors() {
+    let mut failures = Vec::new();
+    for src in programs("errors") {
+        let (out, dir) = compile(&src);
+        fs::remove_dir_all(&dir).ok();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        if out.status.success() {
+            failures.push(format!("{}: compiles, but should not", src.display()));
+            continue;
+        }
+        let want = companion(&src, "err").expect("error program without a .err file");
+        for line in want.lines().filter(|l| !l.is_empty()) {
+            if !stderr.lines().any(|l| l == line) {
+                failures.push(format!("{}: missing `{}` in\n{}", src.display(), line, stderr));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}